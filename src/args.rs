@@ -1,14 +1,36 @@
+use age::x25519;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use glob::Pattern;
+use regex::Regex;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
+use crate::disk_cache::CacheMode;
+use crate::export::ExportFormat;
+use crate::logging::LogFormat;
+use crate::plan::PlanFormat;
+use crate::policies::PolicyDuration;
+use crate::ssm_parameters::SSMParameterFilter;
+
+/// Tiers a write can ask for, Intelligent-Tiering lets SSM pick Standard or Advanced
+pub const WRITE_TIERS: &[&str] = &["Standard", "Advanced", "Intelligent-Tiering"];
+
 #[derive(StructOpt, Debug)]
-#[structopt(name = "ssm_helper",
+#[structopt(
+    name = "ssm_helper",
     about = "AWS Parameter Store Helper - A command line helper for AWS SSM Parameters, written in Rust."
 )]
 pub struct Opt {
-    /// AWS Region
-    #[structopt(short = "r", long = "region", default_value = "us-east-1")]
-    pub region: String,
+    /// AWS Region, then --env, AWS_REGION, the config files and us-east-1
+    #[structopt(short = "r", long = "region")]
+    pub region: Option<String>,
+    /// AWS Profile (shared credentials file), then --env, the config files and the default credential chain
+    #[structopt(short = "p", long = "profile")]
+    pub profile: Option<String>,
+    /// Named environment from the config files: region, profile, endpoint and root prefix of relative names
+    #[structopt(short = "e", long = "env")]
+    pub env: Option<String>,
     /// Decrypt Parameter Value
     #[structopt(short = "d", long = "decrypt")]
     pub decrypt: bool,
@@ -18,6 +40,39 @@ pub struct Opt {
     /// Quiet Mode => Only Errors and Parameter Output
     #[structopt(short = "q", long = "quiet")]
     pub quiet: bool,
+    /// Verbose Mode => -v Debug, -vv Trace
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    pub verbose: u8,
+    /// Log Format [text, json], text if not present nor configured
+    #[structopt(long = "log-format")]
+    pub log_format: Option<LogFormat>,
+    /// Plan every write (put, import, delete, clone, sync, restore, tags, labels), change nothing
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+    /// --dry-run Plan Format [table, json], table if not present nor configured
+    #[structopt(long = "plan-format")]
+    pub plan_format: Option<PlanFormat>,
+    /// Audit Log of every write (JSON lines), ~/.local/state/ssm_helper/audit.jsonl if not present
+    #[structopt(long = "audit-log", env = "SSM_HELPER_AUDIT_LOG", parse(from_os_str))]
+    pub audit_log: Option<PathBuf>,
+    /// Don't record writes in the Audit Log
+    #[structopt(long = "no-audit")]
+    pub no_audit: bool,
+    /// Encrypted disk cache of the parameters read [prefer, refresh, offline], no cache if not present
+    #[structopt(long = "cache-mode")]
+    pub cache_mode: Option<CacheMode>,
+    /// How long a cached parameter is preferred over the API
+    #[structopt(long = "cache-ttl", default_value = "15m", parse(try_from_str = parse_duration))]
+    pub cache_ttl: Duration,
+    /// ~/.cache/ssm_helper if not present
+    #[structopt(long = "cache-dir", parse(from_os_str))]
+    pub cache_dir: Option<PathBuf>,
+    /// Key the cache is encrypted with, outside --cache-dir, ~/.local/state/ssm_helper/cache-key.txt if not present
+    #[structopt(long = "cache-key", parse(from_os_str))]
+    pub cache_key: Option<PathBuf>,
+    /// Refuse every write (put, import, delete, clone, sync, restore, tags, labels)
+    #[structopt(long = "read-only")]
+    pub read_only: bool,
     #[structopt(subcommand)]
     pub cmd: Command,
 }
@@ -27,12 +82,303 @@ pub enum Command {
     /// List All Parameters
     #[structopt(name = "list-all", visible_alias = "la")]
     ListAll,
+    /// List Parameters under a Path
+    #[structopt(name = "list", visible_alias = "ls")]
+    List {
+        /// Parameter Path
+        #[structopt(default_value = "/")]
+        path: String,
+        /// Recurse into sub paths (default)
+        #[structopt(long = "recursive", overrides_with = "no-recursive")]
+        recursive: bool,
+        /// Only the parameters directly under <path>
+        #[structopt(long = "no-recursive", overrides_with = "recursive")]
+        no_recursive: bool,
+        /// Maximum depth below <path> (1 or more)
+        #[structopt(long = "max-depth", parse(try_from_str = parse_depth))]
+        max_depth: Option<usize>,
+        /// Output parameter names only
+        #[structopt(long = "names-only")]
+        names_only: bool,
+        /// Key=Type|KeyId|Label,Option=Equals|BeginsWith,Values=v1,v2 (may be repeated)
+        #[structopt(long = "parameter-filters", number_of_values = 1)]
+        parameter_filters: Vec<SSMParameterFilter>,
+    },
+    /// Tree View of the Parameter Hierarchy under a Path
+    #[structopt(name = "tree")]
+    Tree {
+        /// Parameter Path
+        #[structopt(default_value = "/")]
+        path: String,
+        /// Annotate parameters with their type
+        #[structopt(long = "types")]
+        types: bool,
+        /// Annotate parameters with their version
+        #[structopt(long = "versions")]
+        versions: bool,
+        /// Show parameter counts per path
+        #[structopt(long = "counts")]
+        counts: bool,
+        /// Nested JSON object output (usable as a template context)
+        #[structopt(long = "json")]
+        json: bool,
+    },
+    /// Make Destination Prefix mirror Origin Prefix (create, overwrite changed, delete extraneous)
+    #[structopt(name = "sync")]
+    Sync {
+        /// Origin Prefix Name
+        prefixorigin: String,
+        /// Destination Prefix Name
+        prefixdestination: String,
+        /// AWS Region for the Destination, defaults to --region
+        #[structopt(long = "dest-region")]
+        dest_region: Option<String>,
+        /// AWS Profile for the Destination, defaults to --profile
+        #[structopt(long = "dest-profile")]
+        dest_profile: Option<String>,
+        /// Only sync names (relative to the prefixes) matching these glob patterns (* stays within a segment, ** crosses them)
+        #[structopt(long = "include", number_of_values = 1)]
+        include: Vec<Pattern>,
+        /// Never sync (nor delete) names (relative to the prefixes) matching these glob patterns
+        #[structopt(long = "exclude", number_of_values = 1)]
+        exclude: Vec<Pattern>,
+        /// Tier of created / overwritten parameters, Advanced when a value needs it if not present
+        #[structopt(long = "tier", possible_values = WRITE_TIERS)]
+        tier: Option<String>,
+    },
+    /// Delete Parameters
+    #[structopt(name = "delete", visible_alias = "rm")]
+    Delete {
+        /// Parameter Names
+        #[structopt(required = true)]
+        names: Vec<String>,
+    },
+    /// Export every Parameter under a Path (decrypted) as Kubernetes manifests, one file each,
+    /// Terraform variables or CloudFormation parameters
+    #[structopt(name = "export")]
+    Export {
+        /// Parameter Path
+        path: String,
+        /// Export Format [k8s-secret, k8s-configmap, files, tfvars, tfvars-json, cfn-params, sam-overrides]
+        #[structopt(long = "format")]
+        format: ExportFormat,
+        /// --format files: Directory the files are written to, exported files no parameter maps to anymore are removed
+        #[structopt(long = "out-dir", parse(from_os_str), required_if("format", "files"))]
+        out_dir: Option<PathBuf>,
+        /// --format files: Also write a SHA256SUMS manifest
+        #[structopt(long = "checksums")]
+        checksums: bool,
+        /// --format tfvars / tfvars-json: Also write a variables.tf stub (SecureString values sensitive)
+        #[structopt(long = "variables-tf", parse(from_os_str))]
+        variables_tf: Option<PathBuf>,
+        /// Manifest Name, the last path segment if not present
+        #[structopt(long = "name")]
+        name: Option<String>,
+        /// Manifest Namespace
+        #[structopt(long = "namespace")]
+        namespace: Option<String>,
+        /// Manifest Label, Key=Value (may be repeated)
+        #[structopt(long = "label", parse(try_from_str = parse_key_value), number_of_values = 1)]
+        label: Vec<(String, String)>,
+        /// SecureString Parameters to a Secret, the others to a ConfigMap
+        #[structopt(long = "split-by-type")]
+        split_by_type: bool,
+        /// --format k8s-configmap: Write SecureString Parameters to the ConfigMap anyway, in plain text
+        #[structopt(long = "allow-plaintext-secrets")]
+        allow_plaintext_secrets: bool,
+    },
+    /// Encrypted snapshot of every Parameter under a Path (decrypted values, descriptions, tiers, tags)
+    #[structopt(name = "backup")]
+    Backup {
+        /// Parameter Path
+        path: String,
+        /// Snapshot file, age encrypted
+        #[structopt(long = "out", parse(from_os_str))]
+        out: PathBuf,
+        /// age recipient (age1...), may be repeated, SSM_HELPER_PASSPHRASE is used if not present
+        #[structopt(long = "recipient", number_of_values = 1)]
+        recipient: Vec<x25519::Recipient>,
+    },
+    /// Restore a backup snapshot, previewing the operations first
+    #[structopt(name = "restore")]
+    Restore {
+        /// Snapshot file
+        #[structopt(parse(from_os_str))]
+        snapshot: PathBuf,
+        /// Restore under another prefix, old=new
+        #[structopt(long = "prefix-rewrite", parse(try_from_str = parse_key_value))]
+        prefix_rewrite: Option<(String, String)>,
+        /// age identity file, SSM_HELPER_PASSPHRASE is used if not present
+        #[structopt(long = "identity", parse(from_os_str))]
+        identity: Option<PathBuf>,
+        /// Apply without asking for confirmation
+        #[structopt(long = "yes")]
+        yes: bool,
+    },
+    /// Compare two Paths, possibly from different regions or accounts
+    #[structopt(name = "diff")]
+    Diff {
+        /// Path A
+        path_a: String,
+        /// Path B
+        path_b: String,
+        /// AWS Region for Path B, defaults to --region
+        #[structopt(long = "region-b")]
+        region_b: Option<String>,
+        /// AWS Profile for Path B, defaults to --profile
+        #[structopt(long = "profile-b")]
+        profile_b: Option<String>,
+        /// Show changed values instead of masking them (with --decrypt)
+        #[structopt(long = "show-values")]
+        show_values: bool,
+        /// JSON lines output
+        #[structopt(long = "json")]
+        json: bool,
+    },
     /// Get Parameter by Name (or Path)
     #[structopt(name = "get", visible_alias = "g")]
     Get {
-        /// Parameter Name
+        /// Parameter Name, /name:label or /name:version selects a version,
+        /// /aws/reference/secretsmanager/<secret> reads a Secrets Manager secret (always decrypted)
         #[structopt(required = true, min_values = 1, max_values = 10)]
         name: Vec<String>,
+        /// Output only this key of JSON values, ex: a Secrets Manager secret's password
+        #[structopt(long = "key")]
+        key: Option<String>,
+    },
+    /// Describe Parameter Metadata (description, KMS key, tier, policies...)
+    #[structopt(name = "describe", visible_alias = "desc")]
+    Describe {
+        /// Parameter Name
+        #[structopt(required = true, min_values = 1, max_values = 50)]
+        name: Vec<String>,
+    },
+    /// Find Parameters by name, type, tier, tag, label, KMS key or modification date
+    #[structopt(name = "find", visible_alias = "f")]
+    Find {
+        /// Name contains
+        #[structopt(long = "name-contains", conflicts_with = "begins-with")]
+        name_contains: Option<String>,
+        /// Name begins with
+        #[structopt(long = "begins-with")]
+        begins_with: Option<String>,
+        /// Parameter Type
+        #[structopt(long = "type", possible_values = &["String", "StringList", "SecureString"])]
+        p_type: Option<String>,
+        /// Parameter Tier
+        #[structopt(long = "tier", possible_values = &["Standard", "Advanced"])]
+        tier: Option<String>,
+        /// Tag, Key=Value (may be repeated)
+        #[structopt(long = "tag", parse(try_from_str = parse_key_value), number_of_values = 1)]
+        tag: Vec<(String, String)>,
+        /// Version Label
+        #[structopt(long = "label")]
+        label: Option<String>,
+        /// KMS Key ID
+        #[structopt(long = "key-id")]
+        key_id: Option<String>,
+        /// Modified since, YYYY-MM-DD or RFC3339
+        #[structopt(long = "modified-since", parse(try_from_str = parse_date))]
+        modified_since: Option<DateTime<Utc>>,
+        /// Name matches Regular Expression (client side)
+        #[structopt(long = "regex")]
+        regex: Option<Regex>,
+    },
+    /// Create (or with --overwrite update) a Parameter
+    #[structopt(name = "put", visible_alias = "p")]
+    Put {
+        /// Parameter Name
+        name: String,
+        /// Parameter Value
+        value: String,
+        /// Parameter Type
+        #[structopt(long = "type", default_value = "String", possible_values = &["String", "StringList", "SecureString"])]
+        p_type: String,
+        /// Parameter Description
+        #[structopt(long = "description")]
+        description: Option<String>,
+        /// KMS Key for a SecureString, the account default key if not present
+        #[structopt(long = "key-id")]
+        key_id: Option<String>,
+        /// Regular Expression the value must match
+        #[structopt(long = "allowed-pattern")]
+        allowed_pattern: Option<String>,
+        /// Tag, Key=Value (may be repeated)
+        #[structopt(long = "tag", parse(try_from_str = parse_key_value), number_of_values = 1)]
+        tag: Vec<(String, String)>,
+        /// Parameter Tier, Advanced when the value (over 4KB) or policies need it if not present
+        #[structopt(long = "tier", possible_values = WRITE_TIERS)]
+        tier: Option<String>,
+        /// Expiration Policy (Advanced tier), YYYY-MM-DD or RFC3339, the parameter is deleted then
+        #[structopt(long = "expires-at", parse(try_from_str = parse_date))]
+        expires_at: Option<DateTime<Utc>>,
+        /// ExpirationNotification Policy, EventBridge event this long before --expires-at, ex: 15d, 12h
+        #[structopt(long = "notify-before-expiry", requires = "expires-at")]
+        notify_before_expiry: Option<PolicyDuration>,
+        /// NoChangeNotification Policy, EventBridge event if unchanged for this long, ex: 90d
+        #[structopt(long = "notify-no-change-after")]
+        notify_no_change_after: Option<PolicyDuration>,
+    },
+    /// Put every Parameter from a JSON lines file (`get` / `list` output, with --decrypt)
+    #[structopt(name = "import")]
+    Import {
+        /// JSON lines file, one {"name", "p_type", "value"} document per line
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Tag added to every imported Parameter, Key=Value (may be repeated)
+        #[structopt(long = "tag", parse(try_from_str = parse_key_value), number_of_values = 1)]
+        tag: Vec<(String, String)>,
+        /// Tier of every imported Parameter, Advanced when a value needs it if not present
+        #[structopt(long = "tier", possible_values = WRITE_TIERS)]
+        tier: Option<String>,
+    },
+    /// Add (or replace) Tags on a Parameter
+    #[structopt(name = "tag")]
+    Tag {
+        /// Parameter Name
+        name: String,
+        /// Tags, Key=Value
+        #[structopt(required = true, min_values = 1, parse(try_from_str = parse_key_value))]
+        tags: Vec<(String, String)>,
+    },
+    /// Remove Tags from a Parameter
+    #[structopt(name = "untag")]
+    Untag {
+        /// Parameter Name
+        name: String,
+        /// Tag Keys
+        #[structopt(required = true, min_values = 1)]
+        keys: Vec<String>,
+    },
+    /// Attach Labels to a Parameter Version (a Label already on another version is moved)
+    #[structopt(name = "label")]
+    Label {
+        /// Parameter Name
+        name: String,
+        /// Parameter Version, the latest if not present
+        #[structopt(long = "version")]
+        version: Option<i64>,
+        /// Labels, ex: stable, canary
+        #[structopt(required = true, min_values = 1)]
+        labels: Vec<String>,
+    },
+    /// Remove Labels from a Parameter Version
+    #[structopt(name = "unlabel")]
+    Unlabel {
+        /// Parameter Name
+        name: String,
+        /// Parameter Version
+        #[structopt(long = "version")]
+        version: i64,
+        /// Labels
+        #[structopt(required = true, min_values = 1)]
+        labels: Vec<String>,
+    },
+    /// List the Tags of a Parameter
+    #[structopt(name = "tags")]
+    Tags {
+        /// Parameter Name
+        name: String,
     },
     /// Template - Substitute vars in <templatein> and write to <templateout> or STDOUT
     #[structopt(name = "template", visible_alias = "t")]
@@ -43,6 +389,21 @@ pub enum Command {
         /// Output Template file, stdout if not present
         #[structopt(parse(from_os_str))]
         templateout: Option<PathBuf>,
+        /// Keep running, re-render (atomically) whenever a referenced parameter changes
+        #[structopt(long = "watch", requires = "templateout")]
+        watch: bool,
+        /// --watch poll interval, ex: 30s, 5m (1s or more)
+        #[structopt(long = "interval", default_value = "30s", parse(try_from_str = parse_interval))]
+        interval: Duration,
+        /// Command (sh -c) run after every --watch re-render, ex: "systemctl reload nginx"
+        #[structopt(long = "on-change", requires = "watch")]
+        on_change: Option<String>,
+        /// --on-change retries before waiting for the next change
+        #[structopt(long = "retries", default_value = "3")]
+        retries: u32,
+        /// Ceiling of the doubling delay between --on-change retries and failed polls
+        #[structopt(long = "max-backoff", default_value = "5m", parse(try_from_str = parse_duration))]
+        max_backoff: Duration,
     },
     /// Copy Parameter's Value from origin key to destination key
     #[structopt(name = "clone", visible_alias = "c")]
@@ -51,6 +412,27 @@ pub enum Command {
         origin: String,
         /// Destination Parameter Name
         destination: String,
+        #[structopt(flatten)]
+        target: CloneTarget,
+    },
+    /// Serve Parameters over HTTP (AWS Parameters and Secrets Lambda extension API) with a TTL cache
+    #[structopt(name = "serve")]
+    Serve {
+        /// TCP address to listen on, the Lambda extension port by default
+        #[structopt(long = "listen", default_value = "127.0.0.1:2773")]
+        listen: String,
+        /// Unix socket to listen on instead (created 0600)
+        #[structopt(long = "socket", parse(from_os_str), conflicts_with = "listen")]
+        socket: Option<PathBuf>,
+        /// Cache TTL, ex: 30s, 5m (0s: only concurrent requests are coalesced)
+        #[structopt(long = "ttl", default_value = "5m", parse(try_from_str = parse_duration))]
+        ttl: Duration,
+    },
+    /// Query the local Audit Log of writes
+    #[structopt(name = "audit")]
+    Audit {
+        #[structopt(subcommand)]
+        cmd: AuditCommand,
     },
     /// Recursivelly Copy Parameter's Value Renaming From Origin Prefix to Destination Prefix
     #[structopt(name = "clone-all", visible_alias = "ca")]
@@ -59,5 +441,198 @@ pub enum Command {
         prefixorigin: String,
         /// Destination Prefix Name
         prefixdestination: String,
+        #[structopt(flatten)]
+        target: CloneTarget,
     },
 }
+
+#[derive(StructOpt, Debug)]
+pub enum AuditCommand {
+    /// Show Audit Log entries, oldest first
+    #[structopt(name = "show")]
+    Show {
+        /// Parameter Name glob, ex: /app/prod/*
+        #[structopt(long = "name")]
+        name: Option<Pattern>,
+        /// Action [put, delete, tag, untag, label, unlabel]
+        #[structopt(long = "action", possible_values = &["put", "delete", "tag", "untag", "label", "unlabel"])]
+        action: Option<String>,
+        /// Caller identity (ARN) contains
+        #[structopt(long = "identity")]
+        identity: Option<String>,
+        /// Entries since, YYYY-MM-DD or RFC3339
+        #[structopt(long = "since", parse(try_from_str = parse_date))]
+        since: Option<DateTime<Utc>>,
+        /// Only the last N matching entries
+        #[structopt(long = "last")]
+        last: Option<usize>,
+    },
+}
+
+impl Command {
+    /// Parameter names and paths relative to the --env prefix made absolute
+    pub fn resolve_names<F>(&mut self, resolve: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        let one = |name: &mut String| *name = resolve(name);
+        match self {
+            Command::List { path, .. }
+            | Command::Tree { path, .. }
+            | Command::Backup { path, .. }
+            | Command::Export { path, .. } => one(path),
+            Command::Sync {
+                prefixorigin,
+                prefixdestination,
+                ..
+            }
+            | Command::CloneAll {
+                prefixorigin,
+                prefixdestination,
+                ..
+            } => {
+                one(prefixorigin);
+                one(prefixdestination);
+            }
+            Command::Diff { path_a, path_b, .. } => {
+                one(path_a);
+                one(path_b);
+            }
+            Command::Clone {
+                origin,
+                destination,
+                ..
+            } => {
+                one(origin);
+                one(destination);
+            }
+            Command::Delete { names: name }
+            | Command::Get { name, .. }
+            | Command::Describe { name } => name.iter_mut().for_each(one),
+            Command::Find { begins_with, .. } => begins_with.iter_mut().for_each(one),
+            Command::Put { name, .. }
+            | Command::Tag { name, .. }
+            | Command::Untag { name, .. }
+            | Command::Label { name, .. }
+            | Command::Unlabel { name, .. }
+            | Command::Tags { name } => one(name),
+            Command::ListAll
+            | Command::Restore { .. }
+            | Command::Import { .. }
+            | Command::Template { .. }
+            | Command::Serve { .. }
+            | Command::Audit { .. } => {}
+        }
+    }
+}
+
+/// Destination client, encryption and tags for clone / clone-all
+#[derive(StructOpt, Debug)]
+pub struct CloneTarget {
+    /// AWS Region for the Destination, defaults to --region
+    #[structopt(long = "dest-region")]
+    pub dest_region: Option<String>,
+    /// AWS Profile for the Destination, defaults to --profile
+    #[structopt(long = "dest-profile")]
+    pub dest_profile: Option<String>,
+    /// IAM Role to assume for the Destination (cross account)
+    #[structopt(long = "dest-role-arn")]
+    pub dest_role_arn: Option<String>,
+    /// KMS Key for SecureString Destination Parameters, must exist in the target region / account (the source key within the same region and account, else aws/ssm)
+    #[structopt(long = "dest-kms-key-id")]
+    pub dest_kms_key_id: Option<String>,
+    /// Tag added to the Destination, Key=Value (may be repeated), wins over a copied source tag
+    #[structopt(long = "tag", parse(try_from_str = parse_key_value), number_of_values = 1)]
+    pub tag: Vec<(String, String)>,
+    /// Don't copy the source Parameter's tags
+    #[structopt(long = "no-copy-tags")]
+    pub no_copy_tags: bool,
+    /// Destination Tier, the source tier if not present
+    #[structopt(long = "tier", possible_values = WRITE_TIERS)]
+    pub tier: Option<String>,
+}
+
+/// Parse a Key=Value pair
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(pos) if pos > 0 => Ok((s[..pos].to_string(), s[pos + 1..].to_string())),
+        _ => Err(format!("Invalid Key=Value: {}", s)),
+    }
+}
+
+/// Parse a date, YYYY-MM-DD (midnight UTC) or RFC3339
+pub fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid Date: {} ({})", s, e))
+}
+
+/// --max-depth, 0 would list nothing
+pub fn parse_depth(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(depth) if depth >= 1 => Ok(depth),
+        _ => Err(format!("Invalid Depth: {} (1 or more)", s)),
+    }
+}
+
+/// Parse a duration, ex: 500ms, 30s, 5m, 1h
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let amount: u64 = s[..split]
+        .parse()
+        .map_err(|_| format!("Invalid Duration: {} (ex: 500ms, 30s, 5m, 1h)", s))?;
+    match &s[split..] {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        "h" => Ok(Duration::from_secs(amount * 3600)),
+        _ => Err(format!("Invalid Duration: {} (ex: 500ms, 30s, 5m, 1h)", s)),
+    }
+}
+
+/// Shortest --interval, shorter ones would hammer the API
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// --interval, a duration of `MIN_INTERVAL` or more
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        interval if interval >= MIN_INTERVAL => Ok(interval),
+        _ => Err(format!("Invalid Interval: {} (1s or more)", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("30").is_err());
+    }
+
+    #[test]
+    fn interval_refuses_zero_and_sub_second() {
+        assert_eq!(parse_interval("1s"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_interval("5m"), Ok(Duration::from_secs(300)));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("999ms").is_err());
+        assert!(Opt::from_iter_safe([
+            "ssm_helper",
+            "template",
+            "in",
+            "out",
+            "--watch",
+            "--interval",
+            "0s"
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use failure::Error;
+use glob::Pattern;
+use sha2::{Digest, Sha256};
+
+/// ~/.local/state/ssm_helper/audit.jsonl, None without a home directory
+pub fn default_audit_path() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("ssm_helper").join("audit.jsonl"))
+}
+
+/// One write, as a JSON line. Values are only ever recorded as a hash.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub identity: String,
+    pub region: String,
+    /// put, delete, tag, untag, label, unlabel
+    pub action: String,
+    pub name: String,
+    pub old_version: Option<i64>,
+    pub new_version: Option<i64>,
+    pub value_hash: Option<String>,
+    /// Tag keys / labels involved
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub detail: Vec<String>,
+}
+
+/// sha256:<hex> of a value
+pub fn value_hash(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// Append-only JSON lines file, the caller identity is resolved once per client
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    identity: RefCell<Option<String>>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        AuditLog {
+            path: path.to_path_buf(),
+            identity: RefCell::new(None),
+        }
+    }
+
+    /// Known identity, the STS lookup is skipped
+    #[cfg(test)]
+    pub fn with_identity(path: &Path, identity: &str) -> Self {
+        AuditLog {
+            path: path.to_path_buf(),
+            identity: RefCell::new(Some(identity.to_string())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn identity<F>(&self, resolve: F) -> String
+    where
+        F: FnOnce() -> String,
+    {
+        self.identity
+            .borrow_mut()
+            .get_or_insert_with(resolve)
+            .clone()
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        debug!("Audit: {} {} -> {:?}", entry.action, entry.name, self.path);
+
+        Ok(())
+    }
+}
+
+/// `audit show` filters
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    /// Glob matched against the parameter name
+    pub name: Option<Pattern>,
+    pub action: Option<String>,
+    pub identity: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    /// Only the last N matching entries
+    pub last: Option<usize>,
+}
+
+/// Matching entries, oldest first. Unreadable lines are skipped with a warning.
+pub fn read_audit_log(path: &Path, filter: &AuditFilter) -> Result<Vec<AuditEntry>, Error> {
+    let since = filter
+        .since
+        .map(|s| s.to_rfc3339_opts(SecondsFormat::Millis, true));
+    let mut entries: Vec<AuditEntry> = Vec::new();
+    for (line, text) in fs::read_to_string(path)?.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry = match serde_json::from_str(text) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("{:?} line {}: {}", path, line + 1, e);
+                continue;
+            }
+        };
+        let keep = filter.name.as_ref().is_none_or(|p| p.matches(&entry.name))
+            && filter.action.as_ref().is_none_or(|a| a == &entry.action)
+            && (filter.identity.as_ref()).is_none_or(|i| entry.identity.contains(i.as_str()))
+            && since.as_ref().is_none_or(|s| &entry.timestamp >= s);
+        if keep {
+            entries.push(entry);
+        }
+    }
+    if let Some(last) = filter.last {
+        let skip = entries.len().saturating_sub(last);
+        entries.drain(..skip);
+    }
+
+    Ok(entries)
+}
+
+pub fn now_timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
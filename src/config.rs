@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+
+use crate::guardrails::Guardrails;
+
+/// Project-local config, looked up in the current directory
+pub const PROJECT_CONFIG_FILE: &str = ".ssm_helper.toml";
+pub const DEFAULT_REGION: &str = "us-east-1";
+
+/// ~/.config/ssm_helper/config.toml and ./.ssm_helper.toml, merged in that order
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    /// Custom SSM endpoint, ex: http://localhost:4566 (LocalStack)
+    pub endpoint: Option<String>,
+    /// text, json
+    pub log_format: Option<String>,
+    /// table, json
+    pub plan_format: Option<String>,
+    /// --env NAME
+    pub environments: BTreeMap<String, Environment>,
+    pub guardrails: Guardrails,
+}
+
+/// [environments.NAME], relative names resolve against `prefix`
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Environment {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    pub endpoint: Option<String>,
+    pub prefix: Option<String>,
+}
+
+/// Region, profile, endpoint and root prefix in effect
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub region: String,
+    pub profile: Option<String>,
+    pub endpoint: Option<String>,
+    pub prefix: Option<String>,
+}
+
+impl Settings {
+    /// The endpoint is the configured region's, clients of other regions (--dest-region,
+    /// --region-b) keep their default one
+    pub fn endpoint_for(&self, region: &str) -> Option<&str> {
+        self.endpoint.as_deref().filter(|_| region == self.region)
+    }
+}
+
+impl Config {
+    /// `project` (the project-local file) wins for the region and formats and adds environments
+    /// and guardrails. A checkout mustn't pick the credentials or where signed requests go: its
+    /// profiles and endpoints are ignored, as are environments the user config already defines.
+    pub fn merge(mut self, project: Config) -> Self {
+        let sends_elsewhere = project.profile.is_some()
+            || project.endpoint.is_some()
+            || project
+                .environments
+                .values()
+                .any(|e| e.profile.is_some() || e.endpoint.is_some());
+        if sends_elsewhere {
+            warn!(
+                "{}: profile and endpoint are only read from the user config, ignored",
+                PROJECT_CONFIG_FILE
+            );
+        }
+        for (name, environment) in project.environments {
+            if self.environments.contains_key(&name) {
+                warn!(
+                    "{}: environment '{}' is defined in the user config, ignored",
+                    PROJECT_CONFIG_FILE, name
+                );
+                continue;
+            }
+            let environment = Environment {
+                profile: None,
+                endpoint: None,
+                ..environment
+            };
+            self.environments.insert(name, environment);
+        }
+        Config {
+            region: project.region.or(self.region),
+            profile: self.profile,
+            endpoint: self.endpoint,
+            log_format: project.log_format.or(self.log_format),
+            plan_format: project.plan_format.or(self.plan_format),
+            environments: self.environments,
+            guardrails: self.guardrails.merge(project.guardrails),
+        }
+    }
+
+    /// Flags win over the --env environment, then AWS_REGION (region only), then the
+    /// config defaults
+    pub fn settings(
+        &self,
+        env: Option<&str>,
+        region: Option<String>,
+        profile: Option<String>,
+        region_env: Option<String>,
+    ) -> Result<Settings, Error> {
+        let environment = match env {
+            Some(name) => self.environments.get(name).cloned().ok_or_else(|| {
+                format_err!(
+                    "Unknown environment '{}' (configured: {})",
+                    name,
+                    self.environments
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            })?,
+            None => Environment::default(),
+        };
+
+        Ok(Settings {
+            region: region
+                .or(environment.region)
+                .or(region_env)
+                .or_else(|| self.region.clone())
+                .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            profile: profile
+                .or(environment.profile)
+                .or_else(|| self.profile.clone()),
+            endpoint: environment.endpoint.or_else(|| self.endpoint.clone()),
+            prefix: environment.prefix,
+        })
+    }
+}
+
+/// AWS_REGION, or AWS_DEFAULT_REGION
+pub fn region_from_env() -> Option<String> {
+    ["AWS_REGION", "AWS_DEFAULT_REGION"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|region| !region.is_empty())
+}
+
+/// Names not starting with / are relative to the environment prefix
+pub fn resolve_name(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) if !name.starts_with('/') => {
+            format!("{}/{}", prefix.trim_end_matches('/'), name)
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// $XDG_CONFIG_HOME/ssm_helper/config.toml, ~/.config if not set
+pub fn user_config_path() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("ssm_helper").join("config.toml"))
+}
+
+fn read_config(path: &Path) -> Result<Config, Error> {
+    toml::from_str(&fs::read_to_string(path)?).map_err(|e| format_err!("{:?}: {}", path, e))
+}
+
+/// The user config merged with the project-local one, defaults if there are none
+pub fn load_config() -> Result<Config, Error> {
+    let mut config = match user_config_path() {
+        Some(path) if path.is_file() => read_config(&path)?,
+        _ => Config::default(),
+    };
+    let project = Path::new(PROJECT_CONFIG_FILE);
+    if project.is_file() {
+        config = config.merge(read_config(project)?);
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::ssm_parameters::{relative_name, SSMParameter};
+
+/// Replacement for parameter values in any output unless explicitly asked for
+pub const MASKED_VALUE: &str = "********";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    /// Only on side B
+    Added,
+    /// Only on side A
+    Removed,
+    /// On both sides, with a different type and/or value
+    Changed,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DiffEntry {
+    pub key: String,
+    pub kind: DiffKind,
+    pub type_a: Option<String>,
+    pub type_b: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_a: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_b: Option<String>,
+}
+
+impl DiffEntry {
+    pub fn type_changed(&self) -> bool {
+        self.kind == DiffKind::Changed && self.type_a != self.type_b
+    }
+
+    pub fn value_changed(&self) -> bool {
+        self.kind == DiffKind::Changed && self.value_a.is_some()
+    }
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let t = |t: &Option<String>| t.clone().unwrap_or_default();
+        match self.kind {
+            DiffKind::Added => write!(f, "+ {} [{}]", self.key, t(&self.type_b)),
+            DiffKind::Removed => write!(f, "- {} [{}]", self.key, t(&self.type_a)),
+            DiffKind::Changed => {
+                write!(f, "~ {}", self.key)?;
+                if self.type_changed() {
+                    write!(f, " type: {} => {}", t(&self.type_a), t(&self.type_b))?;
+                } else {
+                    write!(f, " [{}]", t(&self.type_a))?;
+                }
+                if self.value_changed() {
+                    write!(f, " value: {} => {}", t(&self.value_a), t(&self.value_b))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DiffOptions {
+    /// Compare values, only meaningful with decrypted parameters
+    pub compare_values: bool,
+    /// Show compared values instead of masking them
+    pub show_values: bool,
+}
+
+/// Compare two hierarchies by name relative to their own prefix
+pub fn diff_parameters(
+    path_a: &str,
+    side_a: Vec<SSMParameter>,
+    path_b: &str,
+    side_b: Vec<SSMParameter>,
+    options: &DiffOptions,
+) -> Vec<DiffEntry> {
+    let index = |path: &str, side: Vec<SSMParameter>| -> BTreeMap<String, SSMParameter> {
+        side.into_iter()
+            .filter_map(|p| {
+                let key = relative_name(path, p.name.as_ref()?).to_string();
+                Some((key, p))
+            })
+            .collect()
+    };
+    let mut side_a = index(path_a, side_a);
+    let side_b = index(path_b, side_b);
+    let shown = |v: &Option<String>| -> Option<String> {
+        if options.show_values {
+            v.clone()
+        } else {
+            Some(MASKED_VALUE.to_string())
+        }
+    };
+
+    let mut entries: Vec<DiffEntry> = Vec::new();
+    for (key, b) in side_b {
+        match side_a.remove(&key) {
+            None => entries.push(DiffEntry {
+                key,
+                kind: DiffKind::Added,
+                type_a: None,
+                type_b: b.p_type,
+                value_a: None,
+                value_b: None,
+            }),
+            Some(a) => {
+                let value_changed = options.compare_values && a.value != b.value;
+                if a.p_type != b.p_type || value_changed {
+                    entries.push(DiffEntry {
+                        key,
+                        kind: DiffKind::Changed,
+                        value_a: if value_changed { shown(&a.value) } else { None },
+                        value_b: if value_changed { shown(&b.value) } else { None },
+                        type_a: a.p_type,
+                        type_b: b.p_type,
+                    });
+                }
+            }
+        }
+    }
+    entries.extend(side_a.into_iter().map(|(key, a)| DiffEntry {
+        key,
+        kind: DiffKind::Removed,
+        type_a: a.p_type,
+        type_b: None,
+        value_a: None,
+        value_b: None,
+    }));
+    entries.sort_by(|x, y| x.key.cmp(&y.key));
+
+    entries
+}
@@ -0,0 +1,472 @@
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use age::secrecy::ExposeSecret;
+use age::x25519;
+use chrono::{DateTime, SecondsFormat, Utc};
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use crate::output::write_atomically_with_mode;
+use crate::snapshot::{decrypt_snapshot, encrypt_snapshot, SnapshotKey};
+use crate::ssm_parameters::SSMParameter;
+
+/// Key file in the state directory, an age X25519 identity only its owner can read
+pub const CACHE_KEY_FILE: &str = "cache-key.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheMode {
+    /// Fresh entries first, the API otherwise, stale entries if the API fails
+    Prefer,
+    /// Always the API, the cache is only written
+    Refresh,
+    /// Never the API, entries of any age
+    Offline,
+}
+
+impl FromStr for CacheMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefer" => Ok(CacheMode::Prefer),
+            "refresh" => Ok(CacheMode::Refresh),
+            "offline" => Ok(CacheMode::Offline),
+            _ => Err(format!(
+                "Invalid Cache Mode: {} (prefer, refresh, offline)",
+                s
+            )),
+        }
+    }
+}
+
+/// $XDG_CACHE_HOME/ssm_helper, ~/.cache if not set
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("ssm_helper"))
+}
+
+/// $XDG_STATE_HOME/ssm_helper/cache-key.txt, ~/.local/state if not set: away from the entries
+pub fn default_cache_key() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("ssm_helper").join(CACHE_KEY_FILE))
+}
+
+/// One cached read, encrypted at rest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub region: String,
+    pub account: String,
+    /// Requested name, with its selector
+    pub name: String,
+    pub version: Option<i64>,
+    pub with_decryption: bool,
+    pub fetched_at: String,
+    pub parameter: SSMParameter,
+}
+
+/// Parameters cached per region, account, name (and selector) and decryption, values encrypted
+/// with a key held outside the cache directory
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    key: PathBuf,
+    mode: CacheMode,
+    ttl: Duration,
+    /// Profile the account is remembered for, offline mode can't ask STS
+    profile: String,
+    account: RefCell<Option<String>>,
+}
+
+impl DiskCache {
+    /// `key` can't be in `dir`: whoever reads the entries mustn't get the key with them
+    pub fn new(
+        dir: &Path,
+        key: &Path,
+        mode: CacheMode,
+        ttl: Duration,
+        profile: Option<&str>,
+    ) -> Result<Self, Error> {
+        if key.starts_with(dir) {
+            bail!(
+                "The cache key {:?} must be kept outside the cache directory {:?}",
+                key,
+                dir
+            );
+        }
+        Ok(DiskCache {
+            dir: dir.to_path_buf(),
+            key: key.to_path_buf(),
+            mode,
+            ttl,
+            profile: profile.unwrap_or("default").to_string(),
+            account: RefCell::new(None),
+        })
+    }
+
+    /// Known account, STS is never asked
+    #[cfg(test)]
+    pub fn with_account(self, account: &str) -> Self {
+        self.account.replace(Some(account.to_string()));
+        self
+    }
+
+    pub fn with_mode(mut self, mode: CacheMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Account of `region`'s credentials: remembered, then `resolve` (STS) unless offline,
+    /// then the last one seen for this profile
+    pub fn account<F>(&self, region: &str, resolve: F) -> Result<String, Error>
+    where
+        F: FnOnce() -> Result<String, Error>,
+    {
+        if let Some(account) = self.account.borrow().as_ref() {
+            return Ok(account.clone());
+        }
+        let file = self
+            .dir
+            .join(format!("account-{}-{}", self.profile, region));
+        let resolved = match self.mode {
+            CacheMode::Offline => None,
+            _ => match resolve() {
+                Ok(account) => {
+                    fs::create_dir_all(&self.dir)?;
+                    write_atomically_with_mode(&file, account.as_bytes(), 0o600)?;
+                    Some(account)
+                }
+                Err(e) => {
+                    warn!(
+                        "Cache: account unavailable ({}), using the last one seen",
+                        e
+                    );
+                    None
+                }
+            },
+        };
+        let account = match resolved {
+            Some(account) => account,
+            None => fs::read_to_string(&file)
+                .map(|a| a.trim().to_string())
+                .map_err(|_| {
+                    format_err!(
+                        "Cache: no account known for profile {} in {}",
+                        self.profile,
+                        region
+                    )
+                })?,
+        };
+        self.account.replace(Some(account.clone()));
+        Ok(account)
+    }
+
+    /// The cache key, created (0600) on first use
+    fn identity(&self) -> Result<x25519::Identity, Error> {
+        let path = &self.key;
+        if let Ok(contents) = fs::read_to_string(path) {
+            return contents
+                .trim()
+                .parse()
+                .map_err(|e| format_err!("Cache key {:?}: {}", path, e));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let identity = x25519::Identity::generate();
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        writeln!(
+            options.open(path)?,
+            "{}",
+            identity.to_string().expose_secret()
+        )?;
+        debug!("Cache: new key {:?}", path);
+        Ok(identity)
+    }
+
+    fn entry_path(
+        &self,
+        region: &str,
+        account: &str,
+        name: &str,
+        with_decryption: bool,
+    ) -> PathBuf {
+        let digest = Sha256::digest(format!(
+            "{}\n{}\n{}\n{}",
+            region, account, name, with_decryption
+        ));
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.age", hex))
+    }
+
+    /// The entry and whether it is still within the TTL, None if missing or unreadable
+    pub fn read(
+        &self,
+        region: &str,
+        account: &str,
+        name: &str,
+        with_decryption: bool,
+    ) -> Result<Option<(CacheEntry, bool)>, Error> {
+        let path = self.entry_path(region, account, name, with_decryption);
+        let encrypted = match fs::read(&path) {
+            Ok(encrypted) => encrypted,
+            Err(_) => return Ok(None),
+        };
+        let key = SnapshotKey::Identities(vec![self.identity()?]);
+        let entry: CacheEntry = match decrypt_snapshot(&encrypted, &key)
+            .and_then(|plain| serde_json::from_slice(&plain).map_err(Error::from))
+        {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Cache: ignoring {:?}: {}", path, e);
+                return Ok(None);
+            }
+        };
+        let fresh = DateTime::parse_from_rfc3339(&entry.fetched_at)
+            .ok()
+            .and_then(|at| (Utc::now() - at.with_timezone(&Utc)).to_std().ok())
+            .is_some_and(|age| age < self.ttl);
+
+        Ok(Some((entry, fresh)))
+    }
+
+    pub fn write(
+        &self,
+        region: &str,
+        account: &str,
+        name: &str,
+        with_decryption: bool,
+        parameter: &SSMParameter,
+    ) -> Result<(), Error> {
+        let entry = CacheEntry {
+            region: region.to_string(),
+            account: account.to_string(),
+            name: name.to_string(),
+            version: parameter.version,
+            with_decryption,
+            fetched_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            parameter: parameter.clone(),
+        };
+        let key = SnapshotKey::Recipients(vec![self.identity()?.to_public()]);
+        let encrypted = encrypt_snapshot(&serde_json::to_vec(&entry)?, &key)?;
+        fs::create_dir_all(&self.dir)?;
+        write_atomically_with_mode(
+            &self.entry_path(region, account, name, with_decryption),
+            &encrypted,
+            0o600,
+        )?;
+        trace!("Cache: wrote {}", name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_backend::{MemoryBackend, StoredParameter};
+    use crate::ssm_ops::SSMOps;
+    use crate::ssm_parameters::{SSMParametersRequest, SSMPutParameterRequest};
+
+    const TTL: Duration = Duration::from_secs(60);
+    const SECRET: &str = "/aws/reference/secretsmanager/prod/db";
+
+    /// /src/db/pass (version 1) and a Secrets Manager reference, a cache dir and its key apart,
+    /// both removed on drop
+    struct Fixture {
+        backend: MemoryBackend,
+        dir: PathBuf,
+        key: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(test: &str) -> Self {
+            let backend = MemoryBackend::default();
+            for (name, value) in &[("/src/db/pass", "s3cr3t"), (SECRET, "s3cr3t-db")] {
+                backend.insert(StoredParameter {
+                    name: name.to_string(),
+                    p_type: "SecureString".to_string(),
+                    value: value.to_string(),
+                    version: 1,
+                    ..Default::default()
+                });
+            }
+            let tmp = std::env::temp_dir();
+            let dir = tmp.join(format!("ssm_helper_{}_{}", test, std::process::id()));
+            let key = tmp.join(format!(
+                "ssm_helper_{}_key_{}.txt",
+                test,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            let _ = fs::remove_file(&key);
+            Fixture { backend, dir, key }
+        }
+
+        fn cached(&self, mode: CacheMode, ttl: Duration) -> SSMOps {
+            let cache = DiskCache::new(&self.dir, &self.key, mode, ttl, None)
+                .unwrap()
+                .with_account("123456789012");
+            SSMOps::with_client("us-east-1", self.backend.client()).disk_cache(Some(cache))
+        }
+
+        fn get(&self, mode: CacheMode, ttl: Duration, name: &str) -> Result<String, Error> {
+            self.cached(mode, ttl)
+                .get_parameters(&SSMParametersRequest {
+                    names: vec![name.to_string()],
+                    with_decryption: Some(true),
+                })
+                .map(|r| r.parameters[0].value.clone().unwrap())
+        }
+
+        /// Straight to the API, the cache doesn't see it
+        fn put(&self, value: &str) {
+            SSMOps::with_client("us-east-1", self.backend.client())
+                .put_one(SSMPutParameterRequest {
+                    parameter: SSMParameter {
+                        name: Some("/src/db/pass".to_string()),
+                        p_type: Some("SecureString".to_string()),
+                        value: Some(value.to_string()),
+                        ..Default::default()
+                    },
+                    overwrite: true,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+            let _ = fs::remove_file(&self.key);
+        }
+    }
+
+    #[test]
+    fn key_inside_the_cache_dir_is_refused() {
+        let f = Fixture::new("cache_key_inside");
+        let key = f.dir.join("key.txt");
+        assert!(DiskCache::new(&f.dir, &key, CacheMode::Prefer, TTL, None).is_err());
+    }
+
+    #[test]
+    fn offline_without_an_entry_fails() {
+        let f = Fixture::new("cache_offline_empty");
+        assert!(f.get(CacheMode::Offline, TTL, "/src/db/pass").is_err());
+    }
+
+    #[test]
+    fn prefer_serves_a_fresh_entry_over_the_api() {
+        let f = Fixture::new("cache_prefer_fresh");
+        assert_eq!(
+            f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap(),
+            "s3cr3t"
+        );
+        f.put("n3wer");
+        assert_eq!(
+            f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap(),
+            "s3cr3t"
+        );
+    }
+
+    #[test]
+    fn prefer_fetches_again_once_expired() {
+        let f = Fixture::new("cache_prefer_expired");
+        f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap();
+        f.put("n3wer");
+        let expired = Duration::from_secs(0);
+        assert_eq!(
+            f.get(CacheMode::Prefer, expired, "/src/db/pass").unwrap(),
+            "n3wer"
+        );
+    }
+
+    #[test]
+    fn offline_serves_the_cached_entry() {
+        let f = Fixture::new("cache_offline");
+        f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap();
+        f.put("n3wer");
+        assert_eq!(
+            f.get(CacheMode::Offline, TTL, "/src/db/pass").unwrap(),
+            "s3cr3t"
+        );
+    }
+
+    #[test]
+    fn refresh_asks_the_api_and_updates_the_entry() {
+        let f = Fixture::new("cache_refresh");
+        f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap();
+        f.put("n3wer");
+        assert_eq!(
+            f.get(CacheMode::Refresh, TTL, "/src/db/pass").unwrap(),
+            "n3wer"
+        );
+        assert_eq!(
+            f.get(CacheMode::Offline, TTL, "/src/db/pass").unwrap(),
+            "n3wer"
+        );
+    }
+
+    #[test]
+    fn selected_versions_are_entries_of_their_own() {
+        let f = Fixture::new("cache_selected");
+        f.put("n3wer");
+        f.put("n3west");
+        f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap();
+        assert!(f.get(CacheMode::Offline, TTL, "/src/db/pass:2").is_err());
+        assert_eq!(
+            f.get(CacheMode::Prefer, TTL, "/src/db/pass:2").unwrap(),
+            "n3wer"
+        );
+        assert_eq!(
+            f.get(CacheMode::Offline, TTL, "/src/db/pass:2").unwrap(),
+            "n3wer"
+        );
+        assert_eq!(
+            f.get(CacheMode::Offline, TTL, "/src/db/pass").unwrap(),
+            "n3west"
+        );
+    }
+
+    #[test]
+    fn secrets_manager_references_are_cached() {
+        let f = Fixture::new("cache_secret");
+        assert!(f.get(CacheMode::Offline, TTL, SECRET).is_err());
+        assert_eq!(f.get(CacheMode::Prefer, TTL, SECRET).unwrap(), "s3cr3t-db");
+        assert_eq!(f.get(CacheMode::Offline, TTL, SECRET).unwrap(), "s3cr3t-db");
+    }
+
+    #[test]
+    fn entries_are_encrypted_with_a_key_kept_elsewhere() {
+        let f = Fixture::new("cache_encrypted");
+        f.get(CacheMode::Prefer, TTL, "/src/db/pass").unwrap();
+        f.get(CacheMode::Prefer, TTL, SECRET).unwrap();
+        for entry in fs::read_dir(&f.dir).unwrap() {
+            let path = entry.unwrap().path();
+            let contents = fs::read(&path).unwrap();
+            let contents = String::from_utf8_lossy(&contents);
+            assert!(!contents.contains("s3cr3t"), "{:?}", path);
+            assert!(!contents.contains("AGE-SECRET-KEY"), "{:?}", path);
+        }
+        assert!(fs::read_to_string(&f.key)
+            .unwrap()
+            .starts_with("AGE-SECRET-KEY"));
+    }
+}
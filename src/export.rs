@@ -0,0 +1,501 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use base64::Engine;
+use failure::Error;
+use serde_json::value::Value as Json;
+use sha2::{Digest, Sha256};
+
+use crate::output::write_atomically_with_mode;
+use crate::ssm_parameters::{relative_name, SSMParameter};
+
+/// Annotation prefix of the generated manifests
+pub const ANNOTATION_PREFIX: &str = "ssm-helper";
+/// --checksums manifest in the --out-dir, `sha256sum -c` format
+pub const CHECKSUM_MANIFEST: &str = "SHA256SUMS";
+/// Files the last export wrote in the --out-dir, one relative path per line: the only ones pruned
+pub const EXPORT_STATE_FILE: &str = ".ssm_helper_export";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    K8sSecret,
+    K8sConfigMap,
+    /// One file per parameter in --out-dir
+    Files,
+    /// Terraform .tfvars
+    Tfvars,
+    /// Terraform .tfvars.json
+    TfvarsJson,
+    /// `aws cloudformation create-stack --parameters file://...` JSON
+    CfnParams,
+    /// `sam deploy --parameter-overrides` arguments
+    SamOverrides,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "k8s-secret" => Ok(ExportFormat::K8sSecret),
+            "k8s-configmap" => Ok(ExportFormat::K8sConfigMap),
+            "files" => Ok(ExportFormat::Files),
+            "tfvars" => Ok(ExportFormat::Tfvars),
+            "tfvars-json" => Ok(ExportFormat::TfvarsJson),
+            "cfn-params" => Ok(ExportFormat::CfnParams),
+            "sam-overrides" => Ok(ExportFormat::SamOverrides),
+            _ => Err(format!(
+                "Invalid Export Format: {} (k8s-secret, k8s-configmap, files, tfvars, tfvars-json, cfn-params, sam-overrides)",
+                s
+            )),
+        }
+    }
+}
+
+/// Kubernetes metadata of the generated manifests
+#[derive(Debug, Default)]
+pub struct ManifestOptions {
+    /// Last path segment if not present
+    pub name: Option<String>,
+    pub namespace: Option<String>,
+    pub labels: Vec<(String, String)>,
+    /// SecureString parameters go to a Secret, the others to a ConfigMap
+    pub split_by_type: bool,
+    /// A ConfigMap may hold SecureString values, in plain text
+    pub allow_plaintext_secrets: bool,
+}
+
+/// Parameters keyed by their name relative to `path`, two names may not share a key
+fn keyed<'a, F>(
+    path: &str,
+    parameters: &[&'a SSMParameter],
+    key: F,
+) -> Result<BTreeMap<String, &'a SSMParameter>, Error>
+where
+    F: Fn(&str) -> String,
+{
+    let mut keyed: BTreeMap<String, &SSMParameter> = BTreeMap::new();
+    for p in parameters {
+        let name = p.name.as_deref().unwrap_or_default();
+        let k = key(relative_name(path, name));
+        if let Some(other) = keyed.insert(k.clone(), p) {
+            bail!(
+                "{} and {} both export as '{}'",
+                other.name.as_deref().unwrap_or_default(),
+                name,
+                k
+            );
+        }
+    }
+
+    Ok(keyed)
+}
+
+/// Valid ConfigMap / Secret data key: db/host => db_host
+pub fn k8s_key(relative: &str) -> String {
+    relative
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Valid object name (RFC 1123 subdomain): /app/Prod_DB => prod-db
+pub fn k8s_name(name: &str) -> String {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '-' | '.' => c,
+            _ => '-',
+        })
+        .collect();
+    name.trim_matches(|c| c == '-' || c == '.').to_string()
+}
+
+/// YAML double quoted scalar, JSON string escapes are valid YAML
+fn quoted(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Secret / ConfigMap manifests of the parameters under `path` (values decrypted), one YAML
+/// document each. The source path and versions are recorded as annotations.
+pub fn k8s_manifests(
+    format: ExportFormat,
+    path: &str,
+    parameters: &[SSMParameter],
+    options: &ManifestOptions,
+) -> Result<String, Error> {
+    let secure = |p: &SSMParameter| p.p_type.as_deref() == Some("SecureString");
+    let kinds: Vec<(ExportFormat, Vec<&SSMParameter>)> = if options.split_by_type {
+        vec![
+            (
+                ExportFormat::K8sSecret,
+                parameters.iter().filter(|p| secure(p)).collect(),
+            ),
+            (
+                ExportFormat::K8sConfigMap,
+                parameters.iter().filter(|p| !secure(p)).collect(),
+            ),
+        ]
+    } else {
+        vec![(format, parameters.iter().collect())]
+    };
+    if !options.allow_plaintext_secrets {
+        let plaintext: Vec<&str> = kinds
+            .iter()
+            .filter(|(kind, _)| *kind == ExportFormat::K8sConfigMap)
+            .flat_map(|(_, parameters)| parameters.iter().filter(|p| secure(p)))
+            .map(|p| p.name.as_deref().unwrap_or_default())
+            .collect();
+        if !plaintext.is_empty() {
+            bail!(
+                "SecureString parameters would be written to a ConfigMap in plain text: {} \
+                 (use --split-by-type, or --allow-plaintext-secrets)",
+                plaintext.join(", ")
+            );
+        }
+    }
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => k8s_name(
+            path.trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default(),
+        ),
+    };
+    if name.is_empty() {
+        bail!(
+            "No manifest name can be derived from '{}', set --name",
+            path
+        );
+    }
+
+    let mut out = String::new();
+    for (kind, parameters) in kinds {
+        if options.split_by_type && parameters.is_empty() {
+            continue;
+        }
+        let data = keyed(path, &parameters, k8s_key)?;
+        let versions: BTreeMap<&str, i64> = data
+            .iter()
+            .map(|(k, p)| (k.as_str(), p.version.unwrap_or_default()))
+            .collect();
+
+        if !out.is_empty() {
+            out.push_str("---\n");
+        }
+        let secret = kind == ExportFormat::K8sSecret;
+        writeln!(out, "apiVersion: v1")?;
+        writeln!(out, "kind: {}", if secret { "Secret" } else { "ConfigMap" })?;
+        writeln!(out, "metadata:")?;
+        writeln!(out, "  name: {}", quoted(&name))?;
+        if let Some(namespace) = &options.namespace {
+            writeln!(out, "  namespace: {}", quoted(namespace))?;
+        }
+        if !options.labels.is_empty() {
+            writeln!(out, "  labels:")?;
+            for (k, v) in &options.labels {
+                writeln!(out, "    {}: {}", quoted(k), quoted(v))?;
+            }
+        }
+        writeln!(out, "  annotations:")?;
+        writeln!(
+            out,
+            "    {}: {}",
+            quoted(&format!("{}/source-path", ANNOTATION_PREFIX)),
+            quoted(path)
+        )?;
+        writeln!(
+            out,
+            "    {}: {}",
+            quoted(&format!("{}/versions", ANNOTATION_PREFIX)),
+            quoted(&serde_json::to_string(&versions)?)
+        )?;
+        if secret {
+            writeln!(out, "type: Opaque")?;
+        }
+        if data.is_empty() {
+            writeln!(out, "data: {{}}")?;
+            continue;
+        }
+        writeln!(out, "data:")?;
+        for (k, p) in data {
+            let value = p.value.as_deref().unwrap_or_default();
+            let value = match secret {
+                true => base64::engine::general_purpose::STANDARD.encode(value),
+                false => value.to_string(),
+            };
+            writeln!(out, "  {}: {}", quoted(&k), quoted(&value))?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// What `export_files` changed, relative paths
+#[derive(Debug, Default, PartialEq)]
+pub struct FilesExport {
+    /// Files created or whose contents changed
+    pub written: Vec<String>,
+    /// Files no parameter maps to anymore
+    pub removed: Vec<String>,
+}
+
+/// One file (0600) per parameter under `path`, at its relative name in `out_dir`. Each file
+/// is replaced atomically, files a previous export wrote that no parameter maps to anymore are
+/// removed, then their directories if empty. A non-empty `out_dir` without EXPORT_STATE_FILE
+/// is refused: its files aren't ours to prune.
+pub fn export_files(
+    path: &str,
+    parameters: &[SSMParameter],
+    out_dir: &Path,
+    checksums: bool,
+) -> Result<FilesExport, Error> {
+    let parameters: Vec<&SSMParameter> = parameters.iter().collect();
+    let files = keyed(path, &parameters, str::to_string)?;
+    if checksums && files.contains_key(CHECKSUM_MANIFEST) {
+        bail!(
+            "{} would be overwritten by the checksum manifest",
+            CHECKSUM_MANIFEST
+        );
+    }
+    if files.contains_key(EXPORT_STATE_FILE) {
+        bail!(
+            "{} would be overwritten by the export state",
+            EXPORT_STATE_FILE
+        );
+    }
+    let state = out_dir.join(EXPORT_STATE_FILE);
+    let previous: Vec<String> = match fs::read_to_string(&state) {
+        Ok(contents) => contents
+            .lines()
+            .filter(|key| is_relative_file(key))
+            .map(str::to_string)
+            .collect(),
+        Err(_) if fs::read_dir(out_dir).map_or(true, |mut d| d.next().is_none()) => vec![],
+        Err(_) => bail!(
+            "{:?} is not empty and has no {}: not an export directory",
+            out_dir,
+            EXPORT_STATE_FILE
+        ),
+    };
+    let mut keep: BTreeSet<String> = BTreeSet::new();
+    let mut export = FilesExport::default();
+    let mut manifest = String::new();
+    create_private_dir(out_dir)?;
+
+    for (key, p) in &files {
+        if !is_relative_file(key) {
+            bail!("{}: no file name under {:?}", key, out_dir);
+        }
+        let file = out_dir.join(key);
+        let value = p.value.as_deref().unwrap_or_default();
+        if let Some(dir) = file.parent() {
+            create_private_dir(dir)?;
+        }
+        if fs::read(&file).ok().as_deref() != Some(value.as_bytes()) {
+            write_atomically_with_mode(&file, value.as_bytes(), 0o600)?;
+            export.written.push(key.clone());
+        }
+        writeln!(manifest, "{}  {}", sha256_hex(value.as_bytes()), key)?;
+        keep.insert(key.clone());
+    }
+    if checksums {
+        write_atomically_with_mode(&out_dir.join(CHECKSUM_MANIFEST), manifest.as_bytes(), 0o600)?;
+        keep.insert(CHECKSUM_MANIFEST.to_string());
+    }
+
+    for key in previous.into_iter().filter(|key| !keep.contains(key)) {
+        let file = out_dir.join(&key);
+        // Only files, never what a link points to
+        if fs::symlink_metadata(&file).is_ok_and(|m| m.is_file()) {
+            fs::remove_file(&file)?;
+            export.removed.push(key);
+        }
+        // Then its directories, as long as they are empty
+        for dir in file.ancestors().skip(1) {
+            if dir == out_dir || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+    let tracked: String = keep.iter().map(|key| format!("{}\n", key)).collect();
+    write_atomically_with_mode(&state, tracked.as_bytes(), 0o600)?;
+    export.removed.sort();
+    Ok(export)
+}
+
+/// `key` names a file below the export directory (no `..`, no absolute path)
+fn is_relative_file(key: &str) -> bool {
+    !key.is_empty()
+        && Path::new(key)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Directories only their owner can list (0700)
+fn create_private_dir(dir: &Path) -> Result<(), Error> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+    Ok(())
+}
+
+/// Terraform variable name: db/host => db_host, 1st/key => _1st_key
+pub fn tf_identifier(relative: &str) -> String {
+    let name: String = relative
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => name,
+        _ => format!("_{}", name),
+    }
+}
+
+/// CloudFormation parameter name (alphanumeric): db/host_name => DbHostName
+pub fn cfn_identifier(relative: &str) -> String {
+    relative
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn is_string_list(p: &SSMParameter) -> bool {
+    p.p_type.as_deref() == Some("StringList")
+}
+
+/// StringList parameters as lists of strings, the others as strings
+fn variable_value(p: &SSMParameter) -> Json {
+    let value = p.value.clone().unwrap_or_default();
+    match is_string_list(p) {
+        true => Json::Array(
+            value
+                .split(',')
+                .map(|v| Json::from(v.to_string()))
+                .collect(),
+        ),
+        false => Json::from(value),
+    }
+}
+
+/// HCL string literal: JSON escapes, plus ${ and %{ which would start a template sequence
+fn hcl_string(s: &str) -> String {
+    quoted(s).replace("${", "$${").replace("%{", "%%{")
+}
+
+/// Terraform variables or CloudFormation / SAM parameter overrides of the parameters under
+/// `path` (values decrypted). CloudFormation lists (CommaDelimitedList) stay comma separated.
+pub fn variables_output(
+    format: ExportFormat,
+    path: &str,
+    parameters: &[SSMParameter],
+) -> Result<String, Error> {
+    let parameters: Vec<&SSMParameter> = parameters.iter().collect();
+    let mut out = String::new();
+    match format {
+        ExportFormat::Tfvars => {
+            for (name, p) in keyed(path, &parameters, tf_identifier)? {
+                let value = match variable_value(p) {
+                    Json::Array(values) => {
+                        let values: Vec<String> = values
+                            .iter()
+                            .map(|v| hcl_string(v.as_str().unwrap_or_default()))
+                            .collect();
+                        format!("[{}]", values.join(", "))
+                    }
+                    value => hcl_string(value.as_str().unwrap_or_default()),
+                };
+                writeln!(out, "{} = {}", name, value)?;
+            }
+        }
+        ExportFormat::TfvarsJson => {
+            let variables: BTreeMap<String, Json> = keyed(path, &parameters, tf_identifier)?
+                .into_iter()
+                .map(|(name, p)| (name, variable_value(p)))
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&variables)?)?;
+        }
+        ExportFormat::CfnParams => {
+            let overrides: Vec<Json> = keyed(path, &parameters, cfn_identifier)?
+                .into_iter()
+                .map(|(name, p)| {
+                    serde_json::json!({
+                        "ParameterKey": name,
+                        "ParameterValue": p.value.as_deref().unwrap_or_default(),
+                    })
+                })
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&overrides)?)?;
+        }
+        ExportFormat::SamOverrides => {
+            let overrides: Vec<String> = keyed(path, &parameters, cfn_identifier)?
+                .into_iter()
+                .map(|(name, p)| {
+                    format!(
+                        "{}={}",
+                        name,
+                        quoted(p.value.as_deref().unwrap_or_default())
+                    )
+                })
+                .collect();
+            writeln!(out, "{}", overrides.join(" "))?;
+        }
+        _ => bail!("Not a variables format: {:?}", format),
+    }
+
+    Ok(out)
+}
+
+/// variables.tf declaring the variables of a tfvars export, SecureString ones as sensitive
+pub fn terraform_variables(path: &str, parameters: &[SSMParameter]) -> Result<String, Error> {
+    let parameters: Vec<&SSMParameter> = parameters.iter().collect();
+    let mut out = String::new();
+    for (name, p) in keyed(path, &parameters, tf_identifier)? {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        writeln!(out, "variable {} {{", quoted(&name))?;
+        writeln!(
+            out,
+            "  description = {}",
+            hcl_string(p.name.as_deref().unwrap_or_default())
+        )?;
+        match is_string_list(p) {
+            true => writeln!(out, "  type        = list(string)")?,
+            false => writeln!(out, "  type        = string")?,
+        }
+        if p.p_type.as_deref() == Some("SecureString") {
+            writeln!(out, "  sensitive   = true")?;
+        }
+        writeln!(out, "}}")?;
+    }
+
+    Ok(out)
+}
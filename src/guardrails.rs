@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::fmt;
+
+/// [guardrails] in the config files
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Guardrails {
+    /// Every write refused, same as --read-only
+    pub read_only: bool,
+    /// Writes under these prefixes are refused
+    pub read_only_prefixes: Vec<String>,
+    /// Writes under these prefixes need the prefix typed back at an interactive prompt
+    pub confirm_prefixes: Vec<String>,
+}
+
+impl Guardrails {
+    /// Project-local rules add to the user ones, never relax them
+    pub fn merge(mut self, other: Guardrails) -> Self {
+        self.read_only |= other.read_only;
+        self.read_only_prefixes.extend(other.read_only_prefixes);
+        self.confirm_prefixes.extend(other.confirm_prefixes);
+        self
+    }
+
+    /// Ok(Some(prefix)) when the write needs a typed confirmation of `prefix`
+    pub fn check(&self, name: &str) -> Result<Option<&str>, GuardrailError> {
+        let blocked = |rule: GuardrailRule| GuardrailError {
+            rule,
+            name: name.to_string(),
+        };
+        if self.read_only {
+            return Err(blocked(GuardrailRule::ReadOnly));
+        }
+        if let Some(prefix) = self.read_only_prefixes.iter().find(|p| under(name, p)) {
+            return Err(blocked(GuardrailRule::ReadOnlyPrefix(prefix.clone())));
+        }
+
+        Ok(self
+            .confirm_prefixes
+            .iter()
+            .find(|p| under(name, p))
+            .map(String::as_str))
+    }
+}
+
+/// `name` is `prefix` itself or below it, "/prod" and "/prod/" don't cover "/production"
+fn under(name: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty() || name == prefix || name.starts_with(&format!("{}/", prefix))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuardrailRule {
+    ReadOnly,
+    ReadOnlyPrefix(String),
+    ConfirmPrefix(String),
+}
+
+impl fmt::Display for GuardrailRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuardrailRule::ReadOnly => write!(f, "read-only mode"),
+            GuardrailRule::ReadOnlyPrefix(prefix) => write!(f, "read-only prefix '{}'", prefix),
+            GuardrailRule::ConfirmPrefix(prefix) => {
+                write!(f, "confirm prefix '{}' (not confirmed)", prefix)
+            }
+        }
+    }
+}
+
+/// A write refused before any API call
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardrailError {
+    pub rule: GuardrailRule,
+    pub name: String,
+}
+
+impl fmt::Display for GuardrailError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Write to '{}' blocked by guardrail: {}",
+            self.name, self.rule
+        )
+    }
+}
+
+impl std::error::Error for GuardrailError {}
+
+/// Guardrails of one client, a prefix is only confirmed once per run
+#[derive(Debug, Default, Clone)]
+pub struct Guard {
+    rules: Guardrails,
+    confirmed: RefCell<Vec<String>>,
+}
+
+impl Guard {
+    pub fn new(rules: Guardrails) -> Self {
+        Guard {
+            rules,
+            confirmed: RefCell::new(vec![]),
+        }
+    }
+
+    /// `ask` has the user type the prefix back, None (--dry-run) skips the prompt
+    pub fn allow<F>(&self, name: &str, ask: Option<F>) -> Result<(), GuardrailError>
+    where
+        F: FnOnce(&str) -> bool,
+    {
+        let prefix = match self.rules.check(name)? {
+            Some(prefix) => prefix,
+            None => return Ok(()),
+        };
+        if self.confirmed.borrow().iter().any(|p| p == prefix) {
+            return Ok(());
+        }
+        match ask.map(|ask| ask(prefix)) {
+            None => Ok(()),
+            Some(true) => {
+                self.confirmed.borrow_mut().push(prefix.to_string());
+                Ok(())
+            }
+            Some(false) => Err(GuardrailError {
+                rule: GuardrailRule::ConfirmPrefix(prefix.to_string()),
+                name: name.to_string(),
+            }),
+        }
+    }
+}
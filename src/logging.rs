@@ -0,0 +1,89 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use env_logger::Builder;
+use log::LevelFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid Log Format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+/// Maps --quiet / -v occurrences to a level filter
+/// quiet => Error, default => Info, -v => Debug, -vv => Trace
+pub fn level_filter(quiet: bool, verbose: u8) -> LevelFilter {
+    if quiet {
+        return LevelFilter::Error;
+    }
+    match verbose {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Initialize the logging facade, all diagnostics go to STDERR
+/// so parameter output on STDOUT stays clean for piping.
+pub fn init(quiet: bool, verbose: u8, format: LogFormat) {
+    let level = level_filter(quiet, verbose);
+    let mut builder = Builder::new();
+    // Only our own diagnostics get verbose: rusoto logs response bodies (decrypted values)
+    // and signed headers (session token) at debug
+    builder
+        .filter_level(level.min(LevelFilter::Warn))
+        .filter_module("ssm_helper", level);
+
+    match format {
+        LogFormat::Text => {
+            builder.format(|buf, record| writeln!(buf, "[{}] {}", record.level(), record.args()));
+        }
+        LogFormat::Json => {
+            builder.format(|buf, record| {
+                let line = serde_json::json!({
+                    "timestamp": buf.timestamp().to_string(),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{}", line)
+            });
+        }
+    }
+
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_filter_defaults_to_info() {
+        assert_eq!(level_filter(false, 0), LevelFilter::Info);
+    }
+
+    #[test]
+    fn level_filter_follows_verbose() {
+        assert_eq!(level_filter(false, 1), LevelFilter::Debug);
+        assert_eq!(level_filter(false, 2), LevelFilter::Trace);
+        assert_eq!(level_filter(false, 5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn level_filter_quiet_wins_over_verbose() {
+        assert_eq!(level_filter(true, 2), LevelFilter::Error);
+    }
+}
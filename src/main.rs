@@ -10,7 +10,7 @@
 extern crate failure;
 
 use failure::Error;
-use std::process;
+use std::{fs, process};
 
 extern crate serde;
 extern crate serde_json;
@@ -18,41 +18,118 @@ extern crate serde_json;
 extern crate serde_derive;
 
 extern crate rusoto_core;
+extern crate rusoto_credential;
 extern crate rusoto_ssm;
+extern crate rusoto_sts;
 
 extern crate handlebars;
 
+#[macro_use]
+extern crate log;
+
 use structopt::StructOpt;
 
 use args::*;
+use audit::*;
+use config::*;
+use diff::*;
+use disk_cache::*;
+use export::*;
+use guardrails::*;
+use logging::LogFormat;
+use output::*;
+use plan::*;
+use policies::*;
+use serve::*;
+use snapshot::*;
 use ssm_ops::*;
 use ssm_parameters::*;
+use sync::*;
+use tree::*;
+use watch::*;
 
 mod args;
+mod audit;
+mod config;
+mod diff;
+mod disk_cache;
+mod export;
+mod guardrails;
+mod logging;
+#[cfg(test)]
+mod memory_backend;
+mod output;
+mod plan;
+mod policies;
+mod serve;
+mod snapshot;
 mod ssm_ops;
 mod ssm_parameters;
+mod sync;
+mod tree;
+mod watch;
 
 /// AWS SSM Helper
 /// Command Line
-/// --region, -r => Set region for search
+/// --region, -r => Set region for search (else --env, AWS_REGION, the config files, us-east-1)
+/// --profile, -p => AWS profile from the shared credentials file
+/// --env, -e NAME => [environments.NAME] of the config files: region, profile, endpoint and the prefix
+///     relative names (db/host) resolve against
 /// --decrypt, -d => Decrypt parameter value
 /// --quiet => No unnecessary output
+/// --verbose, -v => Debug output, -vv => Trace output
+/// --log-format text|json => Diagnostics format (always on STDERR)
+/// --audit-log FILE, --no-audit => JSON lines record of every write (value hashes only), audit show to query it
+/// --read-only => refuse every write, [guardrails] read-only-prefixes / confirm-prefixes in the config files
+///     (~/.config/ssm_helper/config.toml, ./.ssm_helper.toml) refuse or ask to type the prefix back
+/// --cache-mode prefer|refresh|offline [--cache-ttl 15m] [--cache-dir DIR] => reads through an encrypted disk cache,
+///     offline renders templates from the last values seen
+/// --dry-run [--plan-format table|json] => print the planned writes (masked values, version bumps), change nothing
 /// list-all, lp, all => Lists all parameters
-/// get, g PARAM => get param by name(path)
-/// template, t, FILENAME_IN.tpl [FILENAME_OUT.ext] => parse template and substitute named paths
+/// list, ls [PATH] [--no-recursive] [--max-depth N] [--names-only] [--parameter-filters F] => Lists parameters under PATH
+/// tree [PATH] [--types] [--versions] [--counts] [--json] => parameter hierarchy under PATH
+/// sync ORIGIN DESTINATION [--dest-region R] [--dest-profile P] [--include G] [--exclude G] => mirror a prefix (--dry-run to plan it)
+/// export PATH --format k8s-secret|k8s-configmap [--name N] [--namespace NS] [--label K=V] [--split-by-type]
+///     => decrypted parameters as Kubernetes manifests, SecureString to the Secret with --split-by-type
+///     --format files --out-dir DIR [--checksums] => one 0600 file per parameter, stale files removed
+///     --format tfvars|tfvars-json|cfn-params|sam-overrides [--variables-tf FILE] => Terraform variables
+///     (sensitive SecureString in the variables.tf stub), CloudFormation / SAM parameter overrides
+/// backup PATH --out FILE [--recipient age1...] => age encrypted snapshot (passphrase from SSM_HELPER_PASSPHRASE)
+/// restore FILE [--prefix-rewrite OLD=NEW] [--identity FILE] [--yes] => preview, confirm, then put every changed param
+/// diff PATH_A PATH_B [--region-b R] [--profile-b P] [--show-values] [--json] => added, removed and changed keys
+/// get, g PARAM => get param by name(path), PARAM:label or PARAM:version selects a version
+///     /aws/reference/secretsmanager/SECRET [--key K] => Secrets Manager secret (always decrypted), K of a JSON secret
+/// find, f [--name-contains|--begins-with|--type|--tier|--tag|--label|--key-id|--modified-since|--regex] => search params
+/// describe, desc PARAM => get param metadata (description, key id, tier, policies, last modified user)
+/// put, p NAME VALUE [--type T] [--description D] [--key-id K] [--allowed-pattern RE] [--tag K=V] => create / update a param
+///     [--expires-at DATE] [--notify-before-expiry 15d] [--notify-no-change-after 90d] => policies (Advanced tier)
+///     [--tier Standard|Advanced|Intelligent-Tiering] => also on import, clone and sync, Advanced when a value (over 4KB) or policies need it
+/// import FILE [--tag K=V] => put every param from `get` / `list` JSON lines output
+/// delete, rm NAME... => delete params
+/// serve [--listen 127.0.0.1:2773 | --socket PATH] [--ttl 5m] => local Lambda extension compatible API,
+///     bearer token from SSM_HELPER_SERVE_TOKEN
+/// label NAME [--version N] LABEL..., unlabel NAME --version N LABEL... => version labels
+/// tag NAME K=V..., untag NAME K..., tags NAME => edit / show a param's tags
+/// template, t, FILENAME_IN.tpl [FILENAME_OUT.ext] => parse template and substitute named paths,
+///     {{ssm "/name"}}, {{ssm "/name:stable"}}, {{ssm "/name" version=3}}, {{ssm "/name" label="stable"}}
+///     {{ssm "/aws/reference/secretsmanager/SECRET" key="password"}}
+///     [--watch [--interval 30s] [--on-change CMD] [--retries N] [--max-backoff 5m]] => re-render when a param changes
 /// clone <origin> <destination>, c <origin> <destination> => Copy a Parameter's Value from origin key to destination key
+/// clone-all <origin> <destination>, ca <origin> <destination> => Copy every Parameter under origin prefix to destination prefix
+///     [--dest-region R] [--dest-profile P] [--dest-role-arn ARN] [--dest-kms-key-id K] => copy to another region / account
+///     [--tag K=V] [--no-copy-tags] => extra destination tags, source tags are copied by default
 ///
 /// TODO
 /// Implement:
-/// [ ] Quiet Mode
-/// [ ] Logging
+/// [X] Quiet Mode
+/// [X] Logging
 /// [X] Template Processing
 /// [X] Clone Parameter Value
 /// [X] Fail Crate
 /// [ ] Impl Default for Requests?
 /// Improve:
 /// [X] Pagination calls Input
-/// [ ] Pagination calls Output
+/// [X] Pagination calls Output
 /// [ ] Readme
 /// [ ] Output: human readable != json
 /// [ ] Tests
@@ -69,84 +146,672 @@ fn main() -> Result<(), Error> {
         process::exit(1)
     }
 
-    let options = Opt::from_clap(&clap_options.unwrap());
+    let mut options = Opt::from_clap(&clap_options.unwrap());
+    let config = load_config()?;
+    let settings = config.settings(
+        options.env.as_deref(),
+        options.region.take(),
+        options.profile.take(),
+        region_from_env(),
+    )?;
+    let log_format = match (options.log_format, &config.log_format) {
+        (Some(format), _) => format,
+        (None, Some(format)) => format.parse().map_err(failure::err_msg)?,
+        (None, None) => LogFormat::Text,
+    };
+    let plan_format = match (options.plan_format, &config.plan_format) {
+        (Some(format), _) => format,
+        (None, Some(format)) => format.parse().map_err(failure::err_msg)?,
+        (None, None) => PlanFormat::Table,
+    };
+    options
+        .cmd
+        .resolve_names(|name| resolve_name(settings.prefix.as_deref(), name));
+
+    logging::init(options.quiet, options.verbose, log_format);
 
     let decrypt = options.decrypt;
     let overwrite = options.overwrite;
-    let ssm = SSMOps::new(&options.region);
+    let region = settings.region.clone();
+    let profile = settings.profile.clone();
+    let dry_run = options.dry_run;
+    let audit_path = match options.no_audit {
+        true => None,
+        false => options.audit_log.or_else(default_audit_path),
+    };
+    let guardrails = config.guardrails.merge(Guardrails {
+        read_only: options.read_only,
+        ..Default::default()
+    });
+    // Every client gets the same --dry-run, audit log and guardrails, the endpoint if in its region
+    let configure = |ssm: SSMOps| {
+        let endpoint = settings.endpoint_for(ssm.region());
+        ssm.with_endpoint(endpoint)
+            .dry_run(dry_run)
+            .audit_log(audit_path.as_deref().map(AuditLog::new))
+            .guardrails(guardrails.clone())
+    };
+    let cache_dir = options.cache_dir.or_else(default_cache_dir);
+    let cache_key = options.cache_key.or_else(default_cache_key);
+    let cache = match (options.cache_mode, cache_dir, cache_key) {
+        (Some(mode), Some(dir), Some(key)) => Some(DiskCache::new(
+            &dir,
+            &key,
+            mode,
+            options.cache_ttl,
+            profile.as_deref(),
+        )?),
+        (Some(_), _, _) => {
+            bail!("--cache-mode needs --cache-dir and --cache-key (no home directory)")
+        }
+        (None, _, _) => None,
+    };
+    let ssm = configure(SSMOps::with_profile(&region, profile.as_deref())?).disk_cache(cache);
+    // Writes planned by the other clients (clone / sync destinations)
+    let mut planned: Vec<PlannedOperation> = Vec::new();
 
     match options.cmd {
-        Command::Get { name } => {
-            ssm.get_parameters(&SSMParametersRequest {
+        Command::Get { name, key } => {
+            let mut result = ssm.get_parameters(&SSMParametersRequest {
                 names: name,
                 with_decryption: Some(decrypt),
-            })?
-            .parameters
-            .into_iter()
-            .for_each(|p| println!("{}", serde_json::to_string(&p).unwrap()));
+            })?;
+            if let Some(key) = key {
+                for p in result.parameters.iter_mut() {
+                    let value = p.value.as_deref().unwrap_or_default();
+                    let name = p.name.as_deref().unwrap_or_default();
+                    p.value =
+                        Some(json_key(value, &key).map_err(|e| format_err!("{}: {}", name, e))?);
+                }
+            }
+            print_parameters(result.parameters);
         }
-        Command::ListAll {} => {
-            ssm.get_parameters_by_path(&SSMParametersByPathRequest {
-                path: String::from("/"),
-                recursive: Some(true),
+        Command::Describe { name } => {
+            print_parameters(ssm.describe_parameters(&SSMDescribeParametersRequest {
+                names: name,
+                filters: vec![],
+            })?);
+        }
+        Command::Find {
+            name_contains,
+            begins_with,
+            p_type,
+            tier,
+            tag,
+            label,
+            key_id,
+            modified_since,
+            regex,
+        } => {
+            let mut filters: Vec<SSMParameterFilter> = Vec::new();
+            if let Some(v) = name_contains {
+                filters.push(SSMParameterFilter::new("Name", "Contains", vec![v]));
+            }
+            if let Some(v) = begins_with {
+                filters.push(SSMParameterFilter::new("Name", "BeginsWith", vec![v]));
+            }
+            if let Some(v) = p_type {
+                filters.push(SSMParameterFilter::new("Type", "Equals", vec![v]));
+            }
+            if let Some(v) = tier {
+                filters.push(SSMParameterFilter::new("Tier", "Equals", vec![v]));
+            }
+            if let Some(v) = key_id {
+                filters.push(SSMParameterFilter::new("KeyId", "Equals", vec![v]));
+            }
+            for (k, v) in tag {
+                filters.push(SSMParameterFilter::new(
+                    &format!("tag:{}", k),
+                    "Equals",
+                    vec![v],
+                ));
+            }
+
+            print_parameters(ssm.find_parameters(&SSMFindParametersRequest {
+                filters,
+                label,
+                name_regex: regex,
+                modified_since,
+            })?);
+        }
+        Command::ListAll => {
+            ssm.get_parameters_by_path_paged(
+                &SSMParametersByPathRequest {
+                    path: String::from("/"),
+                    recursive: Some(true),
+                    with_decryption: Some(decrypt),
+                    parameter_filters: vec![],
+                },
+                print_parameters,
+            )
+            .map_err(|e| format_err!("{}", e))?;
+        }
+        Command::List {
+            path,
+            recursive,
+            no_recursive,
+            max_depth,
+            names_only,
+            parameter_filters,
+        } => {
+            // --recursive is the default, one level is exactly what the non recursive call returns
+            let recursive = (recursive || !no_recursive) && max_depth != Some(1);
+            let req = SSMParametersByPathRequest {
+                path,
+                recursive: Some(recursive),
                 with_decryption: Some(decrypt),
+                parameter_filters,
+            };
+            ssm.get_parameters_by_path_paged(&req, |page| {
+                let page = page.into_iter().filter(|p| match (max_depth, &p.name) {
+                    (Some(depth), Some(name)) => relative_depth(&req.path, name) <= depth,
+                    _ => true,
+                });
+                if names_only {
+                    print_names(page);
+                } else {
+                    print_parameters(page);
+                }
             })
-            .unwrap()
-            .parameters
-            .into_iter()
-            .for_each(|p| println!("{}", serde_json::to_string(&p).unwrap()));
+            .map_err(|e| format_err!("{}", e))?;
+        }
+        Command::Tree {
+            path,
+            types,
+            versions,
+            counts,
+            json,
+        } => {
+            let mut tree = ParameterTree::new(&path);
+            ssm.get_parameters_by_path_paged(
+                &SSMParametersByPathRequest {
+                    path,
+                    recursive: Some(true),
+                    with_decryption: Some(decrypt),
+                    parameter_filters: vec![],
+                },
+                |page| page.into_iter().for_each(|p| tree.insert(p)),
+            )
+            .map_err(|e| format_err!("{}", e))?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&tree.to_json())?);
+            } else {
+                println!(
+                    "{}",
+                    tree.render(&TreeOptions {
+                        types,
+                        versions,
+                        counts,
+                    })
+                );
+            }
+        }
+        Command::Sync {
+            prefixorigin,
+            prefixdestination,
+            dest_region,
+            dest_profile,
+            include,
+            exclude,
+            tier,
+        } => {
+            let destination = configure(SSMOps::with_profile(
+                dest_region.as_deref().unwrap_or(&region),
+                dest_profile.as_deref().or(profile.as_deref()),
+            )?);
+            info!("Syncing...");
+
+            let operations = ssm.sync_parameters(
+                &destination,
+                &SSMSyncRequest {
+                    source_path: prefixorigin,
+                    destination_path: prefixdestination,
+                    filter: SyncFilter { include, exclude },
+                    tier,
+                },
+            )?;
+            planned.extend(destination.planned());
+
+            // With --dry-run the destination planned them, printed with the other plans below
+            if !dry_run {
+                operations.iter().for_each(|op| println!("{}", op));
+                info!("Sync Finished! {} operation(s) applied", operations.len());
+            }
+        }
+        Command::Export {
+            path,
+            format,
+            out_dir,
+            checksums,
+            variables_tf,
+            name,
+            namespace,
+            label,
+            split_by_type,
+            allow_plaintext_secrets,
+        } => {
+            let parameters = ssm
+                .get_parameters_by_path(&SSMParametersByPathRequest {
+                    path: path.clone(),
+                    recursive: Some(true),
+                    with_decryption: Some(true),
+                    parameter_filters: vec![],
+                })
+                .map_err(|e| format_err!("{}", e))?
+                .parameters;
+            match (format, out_dir) {
+                (ExportFormat::Files, Some(out_dir)) => {
+                    let export = export_files(&path, &parameters, &out_dir, checksums)?;
+                    info!(
+                        "Export Finished! {} file(s) in {:?}, {} written, {} removed",
+                        parameters.len(),
+                        out_dir,
+                        export.written.len(),
+                        export.removed.len()
+                    );
+                }
+                (ExportFormat::Files, None) => bail!("--format files needs --out-dir"),
+                (ExportFormat::K8sSecret, _) | (ExportFormat::K8sConfigMap, _) => print!(
+                    "{}",
+                    k8s_manifests(
+                        format,
+                        &path,
+                        &parameters,
+                        &ManifestOptions {
+                            name,
+                            namespace,
+                            labels: label,
+                            split_by_type,
+                            allow_plaintext_secrets,
+                        },
+                    )?
+                ),
+                (_, _) => {
+                    let terraform =
+                        matches!(format, ExportFormat::Tfvars | ExportFormat::TfvarsJson);
+                    if variables_tf.is_some() && !terraform {
+                        bail!("--variables-tf needs --format tfvars or tfvars-json");
+                    }
+                    print!("{}", variables_output(format, &path, &parameters)?);
+                    if let Some(file) = variables_tf {
+                        fs::write(&file, terraform_variables(&path, &parameters)?)?;
+                        info!("Terraform variables written to {:?}", file);
+                    }
+                }
+            }
+        }
+        Command::Backup {
+            path,
+            out,
+            recipient,
+        } => {
+            let key = if recipient.is_empty() {
+                SnapshotKey::Passphrase(passphrase_from_env()?)
+            } else {
+                SnapshotKey::Recipients(recipient)
+            };
+            let snapshot = ssm.backup(&path)?;
+            fs::write(
+                &out,
+                encrypt_snapshot(&serde_json::to_vec(&snapshot)?, &key)?,
+            )?;
+            info!(
+                "Backup Finished! {} parameter(s) under {} written to {:?}",
+                snapshot.parameters.len(),
+                path,
+                out
+            );
+        }
+        Command::Restore {
+            snapshot,
+            prefix_rewrite,
+            identity,
+            yes,
+        } => {
+            let key = match identity {
+                Some(file) => SnapshotKey::from_identity_file(&fs::read_to_string(file)?)?,
+                None => SnapshotKey::Passphrase(passphrase_from_env()?),
+            };
+            let snapshot: Snapshot =
+                serde_json::from_slice(&decrypt_snapshot(&fs::read(&snapshot)?, &key)?)?;
+            if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+                bail!(
+                    "Unsupported snapshot format version: {}",
+                    snapshot.format_version
+                );
+            }
+            info!(
+                "Snapshot of {} ({}) taken {}: {} parameter(s)",
+                snapshot.path,
+                snapshot.region,
+                snapshot.created_at,
+                snapshot.parameters.len()
+            );
+            let parameters: Vec<SnapshotParameter> = snapshot
+                .parameters
+                .into_iter()
+                .map(|p| SnapshotParameter {
+                    name: rewrite_prefix(&p.name, &prefix_rewrite),
+                    ..p
+                })
+                .collect();
+
+            let operations = ssm.plan_restore(&parameters)?;
+            if !dry_run {
+                operations.iter().for_each(|op| println!("{}", op));
+            }
+            if operations.is_empty() {
+                info!("Nothing to restore, every parameter is up to date");
+            } else if yes
+                || dry_run
+                || confirm(&format!("Apply {} operation(s)?", operations.len()))?
+            {
+                let restored = ssm.restore(&parameters, &operations)?;
+                info!("Restore Finished! {} parameter(s) written", restored);
+            } else {
+                info!("Restore cancelled");
+            }
+        }
+        Command::Diff {
+            path_a,
+            path_b,
+            region_b,
+            profile_b,
+            show_values,
+            json,
+        } => {
+            let ssm_b = configure(SSMOps::with_profile(
+                region_b.as_deref().unwrap_or(&region),
+                profile_b.as_deref().or(profile.as_deref()),
+            )?);
+            let by_path = |ssm: &SSMOps, path: &str| {
+                ssm.get_parameters_by_path(&SSMParametersByPathRequest {
+                    path: path.to_string(),
+                    recursive: Some(true),
+                    with_decryption: Some(decrypt),
+                    parameter_filters: vec![],
+                })
+                .map(|r| r.parameters)
+                .map_err(|e| format_err!("{}", e))
+            };
+            let side_a = by_path(&ssm, &path_a)?;
+            let side_b = by_path(&ssm_b, &path_b)?;
+
+            let entries = diff_parameters(
+                &path_a,
+                side_a,
+                &path_b,
+                side_b,
+                &DiffOptions {
+                    compare_values: decrypt,
+                    show_values,
+                },
+            );
+            if json {
+                print_parameters(&entries);
+            } else {
+                entries.iter().for_each(|e| println!("{}", e));
+            }
+            info!("{} difference(s) found", entries.len());
+        }
+        Command::Put {
+            name,
+            value,
+            p_type,
+            description,
+            key_id,
+            allowed_pattern,
+            tag,
+            tier,
+            expires_at,
+            notify_before_expiry,
+            notify_no_change_after,
+        } => {
+            let policies = SSMParameterPolicies {
+                expires_at,
+                notify_before_expiry,
+                notify_no_change_after,
+            }
+            .to_json(chrono::Utc::now())
+            .map_err(failure::err_msg)?;
+            ssm.put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: Some(name),
+                    p_type: Some(p_type),
+                    value: Some(value),
+                    ..Default::default()
+                },
+                overwrite,
+                key_id,
+                description,
+                allowed_pattern,
+                tags: tag,
+                tier,
+                policies,
+            })?;
+            info!("Put Finished!");
+        }
+        Command::Import { file, tag, tier } => {
+            let mut parameters: Vec<SSMParameter> = Vec::new();
+            for line in fs::read_to_string(&file)?.lines() {
+                if !line.trim().is_empty() {
+                    parameters.push(serde_json::from_str(line)?);
+                }
+            }
+            let imported = ssm.import_parameters(parameters, overwrite, &tag, tier.as_deref())?;
+            info!("Import Finished! {} parameter(s) from {:?}", imported, file);
+        }
+        Command::Delete { names } => {
+            let deleted = ssm.delete_parameters(&names)?;
+            info!("Delete Finished! {} parameter(s) deleted", deleted.len());
+        }
+        Command::Tag { name, tags } => {
+            ssm.add_tags(&name, &tags)?;
+            info!("Tagged {}", name);
+        }
+        Command::Untag { name, keys } => {
+            ssm.remove_tags(&name, &keys)?;
+            info!("Untagged {}", name);
+        }
+        Command::Label {
+            name,
+            version,
+            labels,
+        } => {
+            let invalid = ssm.label_parameter(&SSMLabelRequest {
+                name,
+                version,
+                labels,
+            })?;
+            if !invalid.is_empty() {
+                let reasons: Vec<String> = invalid
+                    .iter()
+                    .map(|l| format!("'{}' ({})", l, invalid_label_reason(l)))
+                    .collect();
+                bail!("Invalid Labels (not applied): {}", reasons.join(", "));
+            }
+        }
+        Command::Unlabel {
+            name,
+            version,
+            labels,
+        } => {
+            let removed = ssm.unlabel_parameter(&SSMLabelRequest {
+                name,
+                version: Some(version),
+                labels,
+            })?;
+            info!("Removed Labels: {}", removed.join(", "));
+        }
+        Command::Tags { name } => {
+            let tags: serde_json::Map<String, serde_json::Value> = ssm
+                .list_tags(&name)?
+                .into_iter()
+                .map(|(k, v)| (k, serde_json::Value::String(v)))
+                .collect();
+            print_parameters(vec![serde_json::json!({ "name": name, "tags": tags })]);
+        }
+        Command::Template {
+            templatein,
+            templateout: Some(templateout),
+            watch: true,
+            interval,
+            on_change,
+            retries,
+            max_backoff,
+        } => {
+            info!(
+                "Watching Template IN: {:?} - OUT: {:?} every {:?}",
+                templatein, templateout, interval
+            );
+            let mut watcher = TemplateWatcher::new(
+                &ssm,
+                SSMWatchRequest {
+                    template_in: templatein,
+                    template_out: templateout,
+                    interval,
+                    on_change,
+                    retries,
+                    max_backoff,
+                },
+            )?;
+            watcher.run()?;
         }
         Command::Template {
             templatein,
             templateout,
+            ..
         } => {
-            println!(
-                "Processing Template IN: {:#?} - OUT: {:#?}",
+            info!(
+                "Processing Template IN: {:?} - OUT: {:?}",
                 templatein, templateout
             );
 
             match ssm.process_template(templatein, templateout) {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("{}", e);
+                    error!("{}", e);
                     process::exit(1)
                 }
             }
 
-            println!("Processing Finished!");
+            info!("Processing Finished!");
+        }
+        Command::Serve {
+            listen,
+            socket,
+            ttl,
+        } => {
+            let token = std::env::var(SERVE_TOKEN_ENV).ok();
+            if token.is_none() {
+                warn!("No {} set, requests are not authenticated", SERVE_TOKEN_ENV);
+            }
+            let server = Server::new(&ssm, ttl, token);
+            match socket {
+                #[cfg(unix)]
+                Some(path) => serve_unix(server, &path)?,
+                #[cfg(not(unix))]
+                Some(_) => bail!("--socket needs a unix platform"),
+                None => serve_tcp(server, &listen)?,
+            }
+        }
+        Command::Audit {
+            cmd:
+                AuditCommand::Show {
+                    name,
+                    action,
+                    identity,
+                    since,
+                    last,
+                },
+        } => {
+            let path = audit_path
+                .clone()
+                .ok_or_else(|| format_err!("No Audit Log, set --audit-log"))?;
+            print_parameters(read_audit_log(
+                &path,
+                &AuditFilter {
+                    name,
+                    action,
+                    identity,
+                    since,
+                    last,
+                },
+            )?);
         }
         Command::Clone {
             origin,
             destination,
+            target,
         } => {
-            println!("Cloning...");
+            info!("Cloning...");
 
-            match ssm.clone_parameter(origin, destination, overwrite) {
+            let target_ssm = configure(SSMOps::with_credentials(
+                target.dest_region.as_deref().unwrap_or(&region),
+                target.dest_profile.as_deref().or(profile.as_deref()),
+                target.dest_role_arn.as_deref(),
+            )?);
+            let req = SSMCloneRequest {
+                origin,
+                destination,
+                overwrite,
+                key_id: target.dest_kms_key_id,
+                copy_tags: !target.no_copy_tags,
+                tags: target.tag,
+                tier: target.tier,
+            };
+            match ssm.clone_parameter(&target_ssm, &req) {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("{}", e);
+                    error!("{}", e);
                     process::exit(1)
                 }
             }
-            println!("Clone Finished!");
+            planned.extend(target_ssm.planned());
+            info!("Clone Finished!");
         }
         Command::CloneAll {
-            prefixorigin, 
+            prefixorigin,
             prefixdestination,
+            target,
         } => {
-            println!("Cloning...");
+            info!("Cloning...");
 
-            match ssm.clone_recursive(prefixorigin, prefixdestination, overwrite) {
+            let target_ssm = configure(SSMOps::with_credentials(
+                target.dest_region.as_deref().unwrap_or(&region),
+                target.dest_profile.as_deref().or(profile.as_deref()),
+                target.dest_role_arn.as_deref(),
+            )?);
+            let req = SSMCloneRequest {
+                origin: prefixorigin,
+                destination: prefixdestination,
+                overwrite,
+                key_id: target.dest_kms_key_id,
+                copy_tags: !target.no_copy_tags,
+                tags: target.tag,
+                tier: target.tier,
+            };
+            match ssm.clone_recursive(&target_ssm, &req) {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("{}", e);
+                    error!("{}", e);
                     process::exit(1)
                 }
             }
-            
-            println!("Clone Finished!");
+            planned.extend(target_ssm.planned());
+
+            info!("Clone Finished!");
         }
-        _ => (),
+    }
+
+    if dry_run {
+        planned.extend(ssm.planned());
+        print_plan(&planned, plan_format);
+        info!(
+            "Dry Run: {} operation(s) planned, nothing changed",
+            planned.len()
+        );
     }
     Ok(())
 }
@@ -154,12 +819,1173 @@ fn main() -> Result<(), Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use memory_backend::*;
 
     #[test]
     fn it_works() {
         assert_eq!(1, 1); // ;-)
     }
 
+    #[test]
+    fn format_timestamp_rfc3339() {
+        assert_eq!(format_timestamp(0.0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1_577_836_800.512), "2020-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn parse_key_value_pairs() {
+        assert_eq!(
+            parse_key_value("Owner=team=a"),
+            Ok(("Owner".to_string(), "team=a".to_string()))
+        );
+        assert_eq!(
+            parse_key_value("Env="),
+            Ok(("Env".to_string(), "".to_string()))
+        );
+        assert!(parse_key_value("=value").is_err());
+        assert!(parse_key_value("novalue").is_err());
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(
+            format_timestamp(parse_date("2020-01-31").unwrap().timestamp() as f64),
+            "2020-01-31T00:00:00Z"
+        );
+        assert_eq!(
+            format_timestamp(parse_date("2020-01-31T10:00:00+02:00").unwrap().timestamp() as f64),
+            "2020-01-31T08:00:00Z"
+        );
+        assert!(parse_date("31/01/2020").is_err());
+    }
+
+    #[test]
+    fn relative_names() {
+        assert_eq!(relative_name("/app/prod", "/app/prod/db/host"), "db/host");
+        assert_eq!(relative_name("/app/prod/", "/app/prod/db/host"), "db/host");
+        assert_eq!(relative_name("/", "/app/prod"), "app/prod");
+        assert_eq!(relative_name("/app/pro", "/app/prod/db"), "app/prod/db");
+        assert_eq!(relative_depth("/app", "/app/db/host"), 2);
+        assert_eq!(relative_depth("/app", "/app/db"), 1);
+        let begins_with = |v: &str| {
+            vec![SSMParameterFilter::new(
+                "Name",
+                "BeginsWith",
+                vec![v.to_string()],
+            )]
+        };
+        assert_eq!(begins_with_path(&begins_with("/app/pro")), "/app");
+        assert_eq!(begins_with_path(&begins_with("/app/prod/")), "/app/prod");
+        assert_eq!(begins_with_path(&begins_with("/app")), "/");
+        assert_eq!(begins_with_path(&begins_with("app/prod")), "/");
+        assert_eq!(begins_with_path(&[]), "/");
+        assert_eq!(parse_depth("2"), Ok(2));
+        assert!(parse_depth("0").is_err());
+        assert!(parse_depth("-1").is_err());
+    }
+
+    #[test]
+    fn parse_parameter_filters() {
+        let filter: SSMParameterFilter = "Key=Type,Option=Equals,Values=String,SecureString"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            filter,
+            SSMParameterFilter::new(
+                "Type",
+                "Equals",
+                vec!["String".to_string(), "SecureString".to_string()]
+            )
+        );
+        let filter: SSMParameterFilter = "Key=Label,Values=stable".parse().unwrap();
+        assert_eq!(filter.option, None);
+        assert!("Option=Equals".parse::<SSMParameterFilter>().is_err());
+        assert!("Key=Type,String".parse::<SSMParameterFilter>().is_err());
+    }
+
+    fn tree_parameter(name: &str, p_type: &str, value: &str, version: i64) -> SSMParameter {
+        SSMParameter {
+            name: Some(name.to_string()),
+            p_type: Some(p_type.to_string()),
+            value: Some(value.to_string()),
+            version: Some(version),
+            ..Default::default()
+        }
+    }
+
+    fn sample_tree() -> ParameterTree {
+        let mut tree = ParameterTree::new("/app/prod");
+        tree.insert(tree_parameter("/app/prod/db/host", "String", "db.local", 3));
+        tree.insert(tree_parameter("/app/prod/db/port", "String", "5432", 1));
+        tree.insert(tree_parameter(
+            "/app/prod/api_key",
+            "SecureString",
+            "s3cr3t",
+            2,
+        ));
+        tree
+    }
+
+    #[test]
+    fn tree_render() {
+        let rendered = sample_tree().render(&TreeOptions {
+            types: true,
+            versions: true,
+            counts: true,
+        });
+        assert_eq!(
+            rendered,
+            "/app/prod (3)\n\
+             ├── api_key [SecureString v2]\n\
+             └── db (2)\n    \
+             ├── host [String v3]\n    \
+             └── port [String v1]\n\
+             \n1 path, 3 parameters"
+        );
+    }
+
+    #[test]
+    fn tree_json() {
+        let mut tree = sample_tree();
+        tree.insert(tree_parameter("/app/prod/db", "String", "primary", 1));
+        assert_eq!(
+            tree.to_json(),
+            serde_json::json!({
+                "api_key": "s3cr3t",
+                "db": {"_value": "primary", "host": "db.local", "port": "5432"}
+            })
+        );
+    }
+
+    #[test]
+    fn diff_prefixes() {
+        let side_a = vec![
+            tree_parameter("/staging/app/db/host", "String", "staging.db", 1),
+            tree_parameter("/staging/app/db/pass", "SecureString", "a", 1),
+            tree_parameter("/staging/app/debug", "String", "true", 1),
+            tree_parameter("/staging/app/same", "String", "x", 4),
+        ];
+        let side_b = vec![
+            tree_parameter("/prod/app/db/host", "String", "prod.db", 2),
+            tree_parameter("/prod/app/db/pass", "String", "a", 1),
+            tree_parameter("/prod/app/new", "String", "1", 1),
+            tree_parameter("/prod/app/same", "String", "x", 1),
+        ];
+        let options = DiffOptions {
+            compare_values: true,
+            show_values: false,
+        };
+        let entries = diff_parameters("/staging/app", side_a, "/prod/app/", side_b, &options);
+        let lines: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "~ db/host [String] value: ******** => ********",
+                "~ db/pass type: SecureString => String",
+                "- debug [String]",
+                "+ new [String]",
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_plan() {
+        let source = vec![
+            tree_parameter("/a/db/host", "String", "db.local", 1),
+            tree_parameter("/a/db/pass", "SecureString", "new", 2),
+            tree_parameter("/a/same", "String", "x", 1),
+            tree_parameter("/a/skip/me", "String", "x", 1),
+        ];
+        let destination = vec![
+            tree_parameter("/b/db/pass", "SecureString", "old", 1),
+            tree_parameter("/b/same", "String", "x", 7),
+            tree_parameter("/b/stale", "String", "x", 1),
+            tree_parameter("/b/skip/kept", "String", "x", 1),
+        ];
+        let filter = SyncFilter {
+            include: vec![],
+            exclude: vec![glob::Pattern::new("skip/*").unwrap()],
+        };
+        let operations = plan_sync("/a", source, "/b", destination, &filter);
+        let lines: Vec<String> = operations.iter().map(|op| op.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "create    /b/db/host [String] = ********",
+                "overwrite /b/db/pass [SecureString] = ********",
+                "delete    /b/stale [String]",
+            ]
+        );
+        assert_eq!(operations[0].value, Some("db.local".to_string()));
+        // * stops at a /, ** doesn't
+        assert!(filter.matches("skip/deep/key"));
+        let deep = SyncFilter {
+            include: vec![],
+            exclude: vec![glob::Pattern::new("skip/**").unwrap()],
+        };
+        assert!(!deep.matches("skip/deep/key"));
+    }
+
+    #[test]
+    fn sync_dry_run_goes_through_the_planner() {
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        backend.insert(StoredParameter {
+            name: "/dst/stale".to_string(),
+            p_type: "String".to_string(),
+            value: "x".to_string(),
+            version: 2,
+            ..Default::default()
+        });
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        let destination = SSMOps::with_client("us-east-1", backend.client()).dry_run(true);
+
+        ssm.sync_parameters(
+            &destination,
+            &SSMSyncRequest {
+                source_path: "/src".to_string(),
+                destination_path: "/dst".to_string(),
+                filter: SyncFilter::default(),
+                tier: None,
+            },
+        )
+        .unwrap();
+
+        let planned: Vec<(String, String)> = destination
+            .planned()
+            .iter()
+            .map(|op| (op.action.to_string(), op.name.clone()))
+            .collect();
+        assert_eq!(
+            planned,
+            vec![
+                ("create".to_string(), "/dst/db/pass".to_string()),
+                ("delete".to_string(), "/dst/stale".to_string()),
+            ]
+        );
+        assert!(backend.get("/dst/db/pass").is_none());
+        assert!(backend.get("/dst/stale").is_some());
+    }
+
+    fn secure_source(backend: &MemoryBackend) {
+        backend.insert(StoredParameter {
+            name: "/src/db/pass".to_string(),
+            p_type: "SecureString".to_string(),
+            value: "s3cr3t".to_string(),
+            version: 3,
+            key_id: Some("alias/source".to_string()),
+            description: Some("Database password".to_string()),
+            tier: Some("Advanced".to_string()),
+            allowed_pattern: Some("^[a-z0-9]+$".to_string()),
+            policies: Some(
+                r#"[{"Type":"Expiration","Version":"1.0","Attributes":{"Timestamp":"2030-01-01T00:00:00.000Z"}}]"#
+                    .to_string(),
+            ),
+            tags: vec![("Owner".to_string(), "platform".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn clone_secure_string_round_trip() {
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+
+        let req = SSMCloneRequest {
+            origin: "/src/db/pass".to_string(),
+            destination: "/dst/db/pass".to_string(),
+            overwrite: false,
+            key_id: None,
+            copy_tags: true,
+            tags: vec![],
+            tier: None,
+        };
+        ssm.clone_parameter(&ssm, &req).unwrap();
+
+        let source = backend.get("/src/db/pass").unwrap();
+        let clone = backend.get("/dst/db/pass").unwrap();
+        assert_eq!(clone.value, "s3cr3t");
+        assert_eq!(clone.p_type, "SecureString");
+        assert_eq!(clone.version, 1);
+        assert_eq!(clone.key_id, source.key_id);
+        assert_eq!(clone.description, source.description);
+        assert_eq!(clone.tier, source.tier);
+        assert_eq!(clone.allowed_pattern, source.allowed_pattern);
+        assert_eq!(clone.tags, source.tags);
+        let policies: serde_json::Value =
+            serde_json::from_str(clone.policies.as_ref().unwrap()).unwrap();
+        assert_eq!(policies[0]["Type"], "Expiration");
+
+        // Overwrite keeps working (tags go through AddTagsToResource)
+        ssm.clone_parameter(
+            &ssm,
+            &SSMCloneRequest {
+                overwrite: true,
+                ..req
+            },
+        )
+        .unwrap();
+        assert_eq!(backend.get("/dst/db/pass").unwrap().version, 2);
+    }
+
+    #[test]
+    fn clone_secure_string_to_other_account_with_key_override() {
+        let source_backend = MemoryBackend::default();
+        secure_source(&source_backend);
+        let target_backend = MemoryBackend::default();
+        let ssm = SSMOps::with_client("us-east-1", source_backend.client());
+        let target = SSMOps::with_client("eu-west-1", target_backend.client());
+
+        ssm.clone_recursive(
+            &target,
+            &SSMCloneRequest {
+                origin: "/src".to_string(),
+                destination: "/dr".to_string(),
+                overwrite: false,
+                key_id: Some("alias/target".to_string()),
+                copy_tags: true,
+                tags: vec![],
+                tier: None,
+            },
+        )
+        .unwrap();
+
+        let clone = target_backend.get("/dr/db/pass").unwrap();
+        assert_eq!(clone.value, "s3cr3t");
+        assert_eq!(clone.key_id, Some("alias/target".to_string()));
+        assert!(source_backend.get("/dr/db/pass").is_none());
+    }
+
+    #[test]
+    fn clone_secure_string_key_stays_in_its_account_and_region() {
+        let source_backend = MemoryBackend::default();
+        secure_source(&source_backend);
+        let ssm = SSMOps::with_client("us-east-1", source_backend.client());
+        let req = SSMCloneRequest {
+            origin: "/src/db/pass".to_string(),
+            destination: "/dr/db/pass".to_string(),
+            overwrite: false,
+            key_id: None,
+            copy_tags: false,
+            tags: vec![],
+            tier: None,
+        };
+
+        // Another region, then another account: the target's default key, not alias/source
+        let other_region = MemoryBackend::default();
+        ssm.clone_parameter(
+            &SSMOps::with_client("eu-west-1", other_region.client()),
+            &req,
+        )
+        .unwrap();
+        let other_account = MemoryBackend::with_account("210987654321");
+        ssm.clone_parameter(
+            &SSMOps::with_client("us-east-1", other_account.client()),
+            &req,
+        )
+        .unwrap();
+        for backend in &[other_region, other_account] {
+            let clone = backend.get("/dr/db/pass").unwrap();
+            assert_eq!(clone.value, "s3cr3t");
+            assert_eq!(clone.key_id, Some(DEFAULT_KEY_ID.to_string()));
+        }
+    }
+
+    #[test]
+    fn dry_run_plans_without_writing() {
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let ssm = SSMOps::with_client("us-east-1", backend.client()).dry_run(true);
+        let put = |name: &str, overwrite: bool| {
+            ssm.put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: Some(name.to_string()),
+                    p_type: Some("SecureString".to_string()),
+                    value: Some("n3w".to_string()),
+                    ..Default::default()
+                },
+                overwrite,
+                ..Default::default()
+            })
+        };
+
+        put("/dst/db/pass", false).unwrap();
+        put("/src/db/pass", true).unwrap();
+        // Planned exactly as the real write would fail
+        assert!(put("/src/db/pass", false).is_err());
+        // An overwrite's tags are added by a second call, planned as well
+        ssm.put_one(SSMPutParameterRequest {
+            parameter: SSMParameter {
+                name: Some("/src/db/pass".to_string()),
+                p_type: Some("SecureString".to_string()),
+                value: Some("n3w".to_string()),
+                ..Default::default()
+            },
+            overwrite: true,
+            tags: vec![("Team".to_string(), "db".to_string())],
+            ..Default::default()
+        })
+        .unwrap();
+        ssm.add_tags("/src/db/pass", &[("Env".to_string(), "dr".to_string())])
+            .unwrap();
+        let deleted = ssm
+            .delete_parameters(&["/src/db/pass".to_string(), "/missing".to_string()])
+            .unwrap();
+        assert_eq!(deleted, vec!["/src/db/pass".to_string()]);
+
+        let planned = ssm.planned();
+        let summary: Vec<(PlannedAction, &str, Option<i64>, Option<i64>)> = planned
+            .iter()
+            .map(|op| (op.action, op.name.as_str(), op.from_version, op.to_version))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (PlannedAction::Create, "/dst/db/pass", None, Some(1)),
+                (PlannedAction::Overwrite, "/src/db/pass", Some(3), Some(4)),
+                (PlannedAction::Overwrite, "/src/db/pass", Some(3), Some(4)),
+                (PlannedAction::Tag, "/src/db/pass", None, None),
+                (PlannedAction::Tag, "/src/db/pass", None, None),
+                (PlannedAction::Delete, "/src/db/pass", Some(3), None),
+            ]
+        );
+        assert_eq!(planned[0].value.as_deref(), Some(MASKED_VALUE));
+        assert_eq!(planned[3].detail.as_deref(), Some("Team=db"));
+        assert_eq!(
+            render_table(&planned[..2]),
+            [
+                "ACTION     NAME          TYPE          VALUE     VERSION",
+                "create     /dst/db/pass  SecureString  ********  - => v1",
+                "overwrite  /src/db/pass  SecureString  ********  v3 => v4",
+            ]
+            .join("\n")
+        );
+
+        assert!(backend.get("/dst/db/pass").is_none());
+        let source = backend.get("/src/db/pass").unwrap();
+        assert_eq!((source.version, source.value.as_str()), (3, "s3cr3t"));
+        assert_eq!(source.tags.len(), 1);
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+        ssm.add_tags(
+            "/src/db/pass",
+            &[pair("CostCenter", "42"), pair("Owner", "dba")],
+        )
+        .unwrap();
+        ssm.remove_tags("/src/db/pass", &["CostCenter".to_string()])
+            .unwrap();
+        assert_eq!(
+            ssm.list_tags("/src/db/pass").unwrap(),
+            vec![pair("Owner", "dba")]
+        );
+
+        // Extra clone tags win over copied ones, copying can be turned off
+        let req = SSMCloneRequest {
+            origin: "/src/db/pass".to_string(),
+            destination: "/dst/db/pass".to_string(),
+            overwrite: false,
+            key_id: None,
+            copy_tags: true,
+            tags: vec![pair("Owner", "platform"), pair("Env", "dr")],
+            tier: None,
+        };
+        ssm.clone_parameter(&ssm, &req).unwrap();
+        assert_eq!(
+            ssm.list_tags("/dst/db/pass").unwrap(),
+            vec![pair("Env", "dr"), pair("Owner", "platform")]
+        );
+        ssm.clone_parameter(
+            &ssm,
+            &SSMCloneRequest {
+                destination: "/bare/db/pass".to_string(),
+                copy_tags: false,
+                tags: vec![],
+                ..req
+            },
+        )
+        .unwrap();
+        assert!(ssm.list_tags("/bare/db/pass").unwrap().is_empty());
+    }
+
+    #[test]
+    fn import_json_lines() {
+        let backend = MemoryBackend::default();
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        let parameters: Vec<SSMParameter> = vec![
+            r#"{"name":"/app/a","p_type":"String","value":"1","version":4,"arn":null}"#,
+            r#"{"name":"/app/b","p_type":"SecureString","value":"s3cr3t"}"#,
+        ]
+        .into_iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+        let tags = vec![("Source".to_string(), "import".to_string())];
+        assert_eq!(
+            ssm.import_parameters(parameters, false, &tags, None)
+                .unwrap(),
+            2
+        );
+        let b = backend.get("/app/b").unwrap();
+        assert_eq!((b.value.as_str(), b.version), ("s3cr3t", 1));
+        assert_eq!(b.tags.get("Source"), Some(&"import".to_string()));
+
+        let missing_value =
+            vec![serde_json::from_str(r#"{"name":"/app/c","p_type":"String"}"#).unwrap()];
+        assert!(ssm
+            .import_parameters(missing_value, false, &[], None)
+            .is_err());
+    }
+
+    #[test]
+    fn selectors() {
+        assert_eq!(with_selector("/app/db", None, None).unwrap(), "/app/db");
+        assert_eq!(
+            with_selector("/app/db", Some(3), None).unwrap(),
+            "/app/db:3"
+        );
+        assert_eq!(
+            with_selector("/app/db", None, Some("stable")).unwrap(),
+            "/app/db:stable"
+        );
+        assert!(with_selector("/app/db", Some(3), Some("stable")).is_err());
+        assert!(with_selector("/app/db:canary", None, Some("stable")).is_err());
+    }
+
+    #[test]
+    fn custom_endpoint_keeps_sts_on_its_default() {
+        let backend = MemoryBackend::default();
+        let path = std::env::temp_dir().join(format!("ssm_helper_sts_{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        let ssm = SSMOps::with_client("us-east-1", backend.client())
+            .with_endpoint(Some("http://localhost:4566"))
+            .audit_log(Some(AuditLog::new(&path)));
+
+        ssm.put_one(SSMPutParameterRequest {
+            parameter: SSMParameter {
+                name: Some("/app/url".to_string()),
+                p_type: Some("String".to_string()),
+                value: Some("http://x".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(backend.sts_regions(), vec![rusoto_core::Region::UsEast1]);
+        let entries = read_audit_log(&path, &AuditFilter::default()).unwrap();
+        assert_eq!(entries[0].identity, "arn:aws:iam::123456789012:user/test");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn audit_log_records_writes() {
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let path = std::env::temp_dir().join(format!("ssm_helper_audit_{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        let identity = "arn:aws:iam::123456789012:user/ops";
+        let ssm = SSMOps::with_client("us-east-1", backend.client())
+            .audit_log(Some(AuditLog::with_identity(&path, identity)));
+        let put_to = |ssm: &SSMOps, name: &str, value: &str, overwrite: bool| {
+            ssm.put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: Some(name.to_string()),
+                    p_type: Some("SecureString".to_string()),
+                    value: Some(value.to_string()),
+                    ..Default::default()
+                },
+                overwrite,
+                ..Default::default()
+            })
+        };
+        let put = |ssm: &SSMOps, value: &str, overwrite: bool| {
+            put_to(ssm, "/app/db/pass", value, overwrite)
+        };
+
+        put(&ssm, "first-s3cr3t", false).unwrap();
+        put(&ssm, "second-s3cr3t", true).unwrap();
+        // --overwrite of a parameter that didn't exist has no version before
+        put_to(&ssm, "/app/db/user", "app", true).unwrap();
+        ssm.add_tags("/app/db/pass", &[("Owner".to_string(), "dba".to_string())])
+            .unwrap();
+        ssm.label_parameter(&SSMLabelRequest {
+            name: "/app/db/pass".to_string(),
+            version: Some(1),
+            labels: vec!["stable".to_string()],
+        })
+        .unwrap();
+        ssm.delete_parameters(&["/src/db/pass".to_string()])
+            .unwrap();
+        // Nothing is sent in --dry-run, so nothing is recorded
+        let planned = SSMOps::with_client("us-east-1", backend.client())
+            .dry_run(true)
+            .audit_log(Some(AuditLog::with_identity(&path, identity)));
+        put(&planned, "third-s3cr3t", true).unwrap();
+
+        let entries = read_audit_log(&path, &AuditFilter::default()).unwrap();
+        let summary: Vec<(&str, &str, Option<i64>, Option<i64>)> = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.action.as_str(),
+                    e.name.as_str(),
+                    e.old_version,
+                    e.new_version,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("put", "/app/db/pass", None, Some(1)),
+                ("put", "/app/db/pass", Some(1), Some(2)),
+                ("put", "/app/db/user", None, Some(1)),
+                ("tag", "/app/db/pass", None, None),
+                ("label", "/app/db/pass", Some(1), Some(1)),
+                ("delete", "/src/db/pass", Some(3), None),
+            ]
+        );
+        assert!(entries.iter().all(|e| e.identity == identity));
+        assert_eq!(
+            entries[1].value_hash.as_deref(),
+            Some(value_hash("second-s3cr3t").as_str())
+        );
+        assert!(!fs::read_to_string(&path).unwrap().contains("s3cr3t"));
+
+        let filtered = read_audit_log(
+            &path,
+            &AuditFilter {
+                name: Some(glob::Pattern::new("/app/*").unwrap()),
+                action: Some("put".to_string()),
+                last: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(filtered, vec![entries[2].clone()]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn guardrails_block_writes() {
+        let config: Config = toml::from_str(
+            r#"
+            [guardrails]
+            read-only-prefixes = ["/src/"]
+            confirm-prefixes = ["/prod"]
+            "#,
+        )
+        .unwrap();
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let ssm = SSMOps::with_client("us-east-1", backend.client())
+            .guardrails(config.guardrails.clone());
+        let blocked = |err: Error| err.downcast::<GuardrailError>().unwrap();
+
+        let err = ssm
+            .put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: Some("/src/db/pass".to_string()),
+                    p_type: Some("String".to_string()),
+                    value: Some("oops".to_string()),
+                    ..Default::default()
+                },
+                overwrite: true,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(
+            blocked(err).rule,
+            GuardrailRule::ReadOnlyPrefix("/src/".to_string())
+        );
+        let err = ssm
+            .delete_parameters(&["/src/db/pass".to_string()])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Write to '/src/db/pass' blocked by guardrail: read-only prefix '/src/'"
+        );
+        assert_eq!(backend.get("/src/db/pass").unwrap().value, "s3cr3t");
+
+        let read_only = SSMOps::with_client("us-east-1", backend.client()).guardrails(Guardrails {
+            read_only: true,
+            ..Default::default()
+        });
+        let err = read_only
+            .add_tags("/any", &[("K".to_string(), "V".to_string())])
+            .unwrap_err();
+        assert_eq!(blocked(err).rule, GuardrailRule::ReadOnly);
+
+        // A confirm prefix is typed back once per run, "/prod" doesn't cover "/production"
+        let guard = Guard::new(config.guardrails);
+        let refuse = Some(|_: &str| false);
+        assert_eq!(
+            guard.allow("/prod/db/pass", refuse).unwrap_err().rule,
+            GuardrailRule::ConfirmPrefix("/prod".to_string())
+        );
+        guard.allow("/production/x", refuse).unwrap();
+        guard
+            .allow("/prod/db/pass", Some(|prefix: &str| prefix == "/prod"))
+            .unwrap();
+        guard.allow("/prod/db/host", refuse).unwrap();
+    }
+
+    #[test]
+    fn config_environments() {
+        let user: Config = toml::from_str(
+            r#"
+            region = "eu-west-1"
+            profile = "dev"
+            endpoint = "http://localhost:4566"
+            [environments.prod]
+            region = "us-west-2"
+            profile = "prod"
+            prefix = "/app/prod/"
+            "#,
+        )
+        .unwrap();
+        let project: Config = toml::from_str(
+            r#"
+            plan-format = "json"
+            profile = "attacker"
+            endpoint = "https://collector.example"
+            [environments.prod]
+            region = "eu-central-1"
+            [environments.stage]
+            prefix = "/app/stage"
+            endpoint = "https://collector.example"
+            "#,
+        )
+        .unwrap();
+        let config = user.merge(project);
+        assert_eq!(config.plan_format.as_deref(), Some("json"));
+        // A checkout can't redirect the credentials or the requests
+        assert_eq!(config.profile.as_deref(), Some("dev"));
+        assert_eq!(config.endpoint.as_deref(), Some("http://localhost:4566"));
+        assert_eq!(
+            config.environments["prod"].region.as_deref(),
+            Some("us-west-2")
+        );
+        assert_eq!(config.environments["stage"].endpoint, None);
+        let region = |s: Settings| s.region;
+
+        // Flag, then environment, then AWS_REGION, then config, then us-east-1
+        let prod = config
+            .settings(Some("prod"), None, None, Some("ap-south-1".to_string()))
+            .unwrap();
+        assert_eq!(
+            prod,
+            Settings {
+                region: "us-west-2".to_string(),
+                profile: Some("prod".to_string()),
+                endpoint: Some("http://localhost:4566".to_string()),
+                prefix: Some("/app/prod/".to_string()),
+            }
+        );
+        // Clients of other regions don't get the endpoint
+        assert_eq!(
+            prod.endpoint_for("us-west-2"),
+            Some("http://localhost:4566")
+        );
+        assert_eq!(prod.endpoint_for("eu-west-1"), None);
+        let flag = config.settings(Some("prod"), Some("sa-east-1".to_string()), None, None);
+        assert_eq!(region(flag.unwrap()), "sa-east-1");
+        let env_var = config.settings(Some("stage"), None, None, Some("ap-south-1".to_string()));
+        assert_eq!(region(env_var.unwrap()), "ap-south-1");
+        assert_eq!(
+            region(config.settings(None, None, None, None).unwrap()),
+            "eu-west-1"
+        );
+        let default = Config::default().settings(None, None, None, None).unwrap();
+        assert_eq!(
+            (default.region.as_str(), default.profile),
+            ("us-east-1", None)
+        );
+        assert!(config.settings(Some("qa"), None, None, None).is_err());
+
+        let mut options = Opt::from_iter(&[
+            "ssm_helper",
+            "-e",
+            "prod",
+            "clone",
+            "db/host",
+            "/shared/db/host",
+        ]);
+        options
+            .cmd
+            .resolve_names(|name| resolve_name(prod.prefix.as_deref(), name));
+        match options.cmd {
+            Command::Clone {
+                origin,
+                destination,
+                ..
+            } => assert_eq!(
+                (origin.as_str(), destination.as_str()),
+                ("/app/prod/db/host", "/shared/db/host")
+            ),
+            cmd => panic!("{:?}", cmd),
+        }
+        assert_eq!(resolve_name(None, "db/host"), "db/host");
+    }
+
+    #[test]
+    fn labels_and_selectors() {
+        let backend = MemoryBackend::default();
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        let put = |value: &str, overwrite: bool| {
+            ssm.put_one(SSMPutParameterRequest {
+                parameter: tree_parameter("/app/db", "String", value, 0),
+                overwrite,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        put("reviewed", false);
+        put("latest", true);
+        let label = |version: Option<i64>, labels: &[&str]| SSMLabelRequest {
+            name: "/app/db".to_string(),
+            version,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+        };
+        assert_eq!(
+            ssm.label_parameter(&label(Some(1), &["stable", "1bad"]))
+                .unwrap(),
+            vec!["1bad"]
+        );
+        assert_eq!(invalid_label_reason("1bad"), "can't start with a number");
+        assert_eq!(
+            invalid_label_reason("AWSCURRENT"),
+            "can't start with aws or ssm"
+        );
+        assert_eq!(
+            invalid_label_reason("a/b"),
+            "only letters, numbers, periods, hyphens and underscores"
+        );
+        ssm.label_parameter(&label(None, &["canary"])).unwrap();
+
+        let get = |names: &[&str]| {
+            ssm.get_parameters(&SSMParametersRequest {
+                names: names.iter().map(|n| n.to_string()).collect(),
+                with_decryption: Some(true),
+            })
+        };
+        let result = get(&["/app/db:stable", "/app/db:2", "/app/db:canary"]).unwrap();
+        let values: Vec<(Option<String>, Option<String>)> = result
+            .parameters
+            .into_iter()
+            .map(|p| (p.value, p.selector))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (Some("reviewed".to_string()), Some(":stable".to_string())),
+                (Some("latest".to_string()), Some(":2".to_string())),
+                (Some("latest".to_string()), Some(":canary".to_string())),
+            ]
+        );
+
+        let dir = std::env::temp_dir().join(format!("ssm_helper_labels_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("in.tpl"),
+            r#"{{ssm "/app/db" label="stable"}} {{ssm "/app/db" version=2}} {{ssm "/app/db:canary"}}"#,
+        )
+        .unwrap();
+        ssm.process_template(dir.join("in.tpl"), Some(dir.join("out")))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("out")).unwrap(),
+            "reviewed latest latest"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            ssm.unlabel_parameter(&label(Some(1), &["stable", "canary"]))
+                .unwrap(),
+            vec!["stable"]
+        );
+        assert!(get(&["/app/db:stable"]).is_err());
+    }
+
+    #[test]
+    fn parameter_policies() {
+        let now = parse_date("2020-01-01").unwrap();
+        assert_eq!(
+            "15d".parse::<PolicyDuration>(),
+            Ok(PolicyDuration {
+                amount: 15,
+                unit: PolicyUnit::Days
+            })
+        );
+        assert!("0h".parse::<PolicyDuration>().is_err());
+        assert!("15w".parse::<PolicyDuration>().is_err());
+
+        let policies = SSMParameterPolicies {
+            expires_at: Some(parse_date("2020-03-01").unwrap()),
+            notify_before_expiry: Some("15d".parse().unwrap()),
+            notify_no_change_after: Some("12h".parse().unwrap()),
+        };
+        let json = policies.to_json(now).unwrap().unwrap();
+        let summaries: Vec<String> = serde_json::from_str::<Vec<serde_json::Value>>(&json)
+            .unwrap()
+            .iter()
+            .filter_map(|p| policy_summary(&p.to_string()))
+            .collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "Expires at 2020-03-01T00:00:00.000Z",
+                "Notify 15 days before expiration",
+                "Notify if unchanged for 12 hours",
+            ]
+        );
+
+        assert_eq!(SSMParameterPolicies::default().to_json(now), Ok(None));
+        let past = SSMParameterPolicies {
+            expires_at: Some(parse_date("2019-12-31").unwrap()),
+            ..Default::default()
+        };
+        assert!(past.to_json(now).is_err());
+        let too_early = SSMParameterPolicies {
+            expires_at: Some(parse_date("2020-01-10").unwrap()),
+            notify_before_expiry: Some("10d".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(too_early.to_json(now).is_err());
+
+        // describe shows them readable
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        let described = ssm
+            .describe_parameters(&SSMDescribeParametersRequest {
+                names: vec!["/src/db/pass".to_string()],
+                filters: vec![],
+            })
+            .unwrap();
+        assert_eq!(
+            described[0].policies[0].summary.as_deref(),
+            Some("Expires at 2030-01-01T00:00:00.000Z")
+        );
+    }
+
+    #[test]
+    fn tier_selection() {
+        let large = STANDARD_TIER_MAX_VALUE_SIZE + 1;
+        assert_eq!(select_tier(None, 10, false), Ok(None));
+        assert_eq!(
+            select_tier(None, large, false),
+            Ok(Some("Advanced".to_string()))
+        );
+        assert_eq!(
+            select_tier(None, 10, true),
+            Ok(Some("Advanced".to_string()))
+        );
+        assert_eq!(
+            select_tier(Some("Intelligent-Tiering"), large, false),
+            Ok(Some("Intelligent-Tiering".to_string()))
+        );
+        assert!(select_tier(Some("Standard"), large, false).is_err());
+        assert!(select_tier(Some("Advanced"), ADVANCED_TIER_MAX_VALUE_SIZE + 1, false).is_err());
+
+        // An oversized value goes Advanced, and clone preserves it
+        let backend = MemoryBackend::default();
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        ssm.put_one(SSMPutParameterRequest {
+            parameter: tree_parameter("/app/cert", "String", &"x".repeat(large), 0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            backend.get("/app/cert").unwrap().tier.as_deref(),
+            Some("Advanced")
+        );
+        let req = SSMCloneRequest {
+            origin: "/app/cert".to_string(),
+            destination: "/dr/cert".to_string(),
+            overwrite: false,
+            key_id: None,
+            copy_tags: true,
+            tags: vec![],
+            tier: None,
+        };
+        ssm.clone_parameter(&ssm, &req).unwrap();
+        assert_eq!(
+            backend.get("/dr/cert").unwrap().tier.as_deref(),
+            Some("Advanced")
+        );
+        let standard = SSMCloneRequest {
+            destination: "/std/cert".to_string(),
+            tier: Some("Standard".to_string()),
+            ..req
+        };
+        assert!(ssm.clone_parameter(&ssm, &standard).is_err());
+    }
+
+    #[test]
+    fn secrets_manager_references() {
+        assert_eq!(
+            json_key(r#"{"username":"app","port":5432}"#, "username"),
+            Ok("app".to_string())
+        );
+        assert_eq!(json_key(r#"{"port":5432}"#, "port"), Ok("5432".to_string()));
+        assert!(json_key(r#"{"port":5432}"#, "password").is_err());
+        assert!(json_key("plain", "password").is_err());
+        assert!(is_reserved_name(
+            "/aws/service/ami-amazon-linux-latest/amzn2-ami-hvm-x86_64-gp2"
+        ));
+        assert!(is_reserved_name("ssm-owned"));
+        assert!(is_reserved_name("AWS"));
+        assert!(!is_reserved_name("/app/aws"));
+        // Near misses are ordinary names
+        assert!(!is_reserved_name("/awsome/app"));
+        assert!(!is_reserved_name("/ssmtest/x"));
+        assert!(!is_reserved_name("aws2/x"));
+
+        let backend = MemoryBackend::default();
+        let secret = "/aws/reference/secretsmanager/prod/db";
+        backend.insert(StoredParameter {
+            name: secret.to_string(),
+            p_type: "SecureString".to_string(),
+            value: r#"{"username":"app","password":"s3cr3t"}"#.to_string(),
+            version: 1,
+            key_id: Some(DEFAULT_KEY_ID.to_string()),
+            ..Default::default()
+        });
+        backend.insert(StoredParameter {
+            name: "/app/db/host".to_string(),
+            p_type: "String".to_string(),
+            value: "db.local".to_string(),
+            version: 1,
+            ..Default::default()
+        });
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+
+        // No --decrypt needed, and mixed with regular names
+        let result = ssm
+            .get_parameters(&SSMParametersRequest {
+                names: vec![
+                    "/app/db/host".to_string(),
+                    secret.to_string(),
+                    "/aws/reference/secretsmanager/missing".to_string(),
+                ],
+                with_decryption: Some(false),
+            })
+            .unwrap();
+        assert_eq!(result.parameters.len(), 2);
+        assert_eq!(
+            result.parameters[1].value.as_deref(),
+            Some(r#"{"username":"app","password":"s3cr3t"}"#)
+        );
+        assert_eq!(
+            result.invalid_parameters,
+            vec!["/aws/reference/secretsmanager/missing"]
+        );
+
+        let dir = std::env::temp_dir().join(format!("ssm_helper_secrets_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("in.tpl"),
+            r#"{{ssm "/app/db/host"}}:{{ssm "/aws/reference/secretsmanager/prod/db" key="password"}}"#,
+        )
+        .unwrap();
+        ssm.process_template(dir.join("in.tpl"), Some(dir.join("out")))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("out")).unwrap(),
+            "db.local:s3cr3t"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(ssm
+            .get_parameters_by_path(&SSMParametersByPathRequest {
+                path: "/aws/reference/secretsmanager".to_string(),
+                with_decryption: Some(true),
+                recursive: Some(true),
+                parameter_filters: vec![],
+            })
+            .is_err());
+        assert!(ssm
+            .put_one(SSMPutParameterRequest {
+                parameter: tree_parameter(secret, "String", "x", 0),
+                overwrite: true,
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        use age::secrecy::ExposeSecret;
+
+        let rewrite = Some(("/src".to_string(), "/restored".to_string()));
+        assert_eq!(
+            rewrite_prefix("/src/db/pass", &rewrite),
+            "/restored/db/pass"
+        );
+        assert_eq!(rewrite_prefix("/src", &rewrite), "/restored");
+        assert_eq!(rewrite_prefix("/srcs/db", &rewrite), "/srcs/db");
+        assert_eq!(rewrite_prefix("/src/db", &None), "/src/db");
+
+        let backend = MemoryBackend::default();
+        secure_source(&backend);
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        let snapshot = ssm.backup("/src").unwrap();
+        assert_eq!(snapshot.parameters.len(), 1);
+        let saved = &snapshot.parameters[0];
+        assert_eq!(saved.value, "s3cr3t");
+        assert_eq!(saved.tier.as_deref(), Some("Advanced"));
+        assert_eq!(
+            saved.tags.get("Owner").map(String::as_str),
+            Some("platform")
+        );
+
+        let identity = age::x25519::Identity::generate();
+        let encrypted = encrypt_snapshot(
+            &serde_json::to_vec(&snapshot).unwrap(),
+            &SnapshotKey::Recipients(vec![identity.to_public()]),
+        )
+        .unwrap();
+        assert!(!String::from_utf8_lossy(&encrypted).contains("s3cr3t"));
+        let identity_file = format!("# test\n{}\n", identity.to_string().expose_secret());
+        let key = SnapshotKey::from_identity_file(&identity_file).unwrap();
+        let restored: Snapshot =
+            serde_json::from_slice(&decrypt_snapshot(&encrypted, &key).unwrap()).unwrap();
+        assert!(
+            decrypt_snapshot(&encrypted, &SnapshotKey::Passphrase("nope".to_string())).is_err()
+        );
+
+        let parameters: Vec<SnapshotParameter> = restored
+            .parameters
+            .into_iter()
+            .map(|p| SnapshotParameter {
+                name: rewrite_prefix(&p.name, &rewrite),
+                ..p
+            })
+            .collect();
+        let operations = ssm.plan_restore(&parameters).unwrap();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].action, SyncAction::Create);
+        assert_eq!(ssm.restore(&parameters, &operations).unwrap(), 1);
+        let copy = backend.get("/restored/db/pass").unwrap();
+        assert_eq!(copy.value, "s3cr3t");
+        assert_eq!(copy.description.as_deref(), Some("Database password"));
+        assert_eq!(copy.tags.get("Owner").map(String::as_str), Some("platform"));
+
+        // Replaying the same snapshot is a no-op
+        assert!(ssm.plan_restore(&parameters).unwrap().is_empty());
+    }
+
     #[test]
     fn get_parameter() {
         let decrypt = true;
@@ -171,7 +1997,7 @@ mod tests {
         });
         assert!(result.is_ok());
         let unw_result = result.unwrap();
-        assert!(unw_result.parameters.len() > 0);
+        assert!(!unw_result.parameters.is_empty());
         assert_eq!(unw_result.invalid_parameters.len(), 0);
     }
 
@@ -212,13 +2038,14 @@ mod tests {
         let path = "/".to_string();
         let recursive = true;
         let result = ssm.get_parameters_by_path(&SSMParametersByPathRequest {
-            path: path,
+            path,
             with_decryption: Some(decrypt),
             recursive: Some(recursive),
+            parameter_filters: vec![],
         });
         assert!(result.is_ok());
         let unw_result = result.unwrap();
-        assert!(unw_result.parameters.len() > 0);
+        assert!(!unw_result.parameters.is_empty());
         assert_eq!(unw_result.invalid_parameters.len(), 0);
     }
 
@@ -229,10 +2056,186 @@ mod tests {
         let path = "*".to_string();
         let recursive = true;
         let result = ssm.get_parameters_by_path(&SSMParametersByPathRequest {
-            path: path,
+            path,
             with_decryption: Some(decrypt),
             recursive: Some(recursive),
+            parameter_filters: vec![],
         });
         assert!(result.is_err());
     }
+
+    #[test]
+    fn export_k8s_manifests() {
+        let parameters = vec![
+            tree_parameter("/app/prod/db/host", "String", "db.internal", 3),
+            tree_parameter("/app/prod/db/pass", "SecureString", "s3cr3t", 7),
+        ];
+        let options = ManifestOptions {
+            namespace: Some("web".to_string()),
+            labels: vec![("team".to_string(), "platform".to_string())],
+            ..Default::default()
+        };
+
+        let secret =
+            k8s_manifests(ExportFormat::K8sSecret, "/app/prod", &parameters, &options).unwrap();
+        assert_eq!(
+            secret,
+            r#"apiVersion: v1
+kind: Secret
+metadata:
+  name: "prod"
+  namespace: "web"
+  labels:
+    "team": "platform"
+  annotations:
+    "ssm-helper/source-path": "/app/prod"
+    "ssm-helper/versions": "{\"db_host\":3,\"db_pass\":7}"
+type: Opaque
+data:
+  "db_host": "ZGIuaW50ZXJuYWw="
+  "db_pass": "czNjcjN0"
+"#
+        );
+
+        let split = k8s_manifests(
+            ExportFormat::K8sConfigMap,
+            "/app/prod",
+            &parameters,
+            &ManifestOptions {
+                name: Some("app".to_string()),
+                split_by_type: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let documents: Vec<&str> = split.split("---\n").collect();
+        assert_eq!(documents.len(), 2);
+        assert!(
+            documents[0].contains("kind: Secret")
+                && documents[0].contains("\"db_pass\": \"czNjcjN0\"")
+        );
+        assert!(!documents[0].contains("db_host"));
+        assert!(
+            documents[1].contains("kind: ConfigMap")
+                && documents[1].contains("\"db_host\": \"db.internal\"")
+        );
+        assert!(!documents[1].contains("db_pass") && !split.contains("s3cr3t"));
+
+        // SecureString values only go to a ConfigMap when asked to
+        let configmap = |allow_plaintext_secrets: bool| {
+            k8s_manifests(
+                ExportFormat::K8sConfigMap,
+                "/app/prod",
+                &parameters,
+                &ManifestOptions {
+                    allow_plaintext_secrets,
+                    ..Default::default()
+                },
+            )
+        };
+        let refused = configmap(false).unwrap_err().to_string();
+        assert!(refused.contains("/app/prod/db/pass") && refused.contains("--split-by-type"));
+        assert!(configmap(true).unwrap().contains("\"db_pass\": \"s3cr3t\""));
+
+        // Two names may not end up under the same key
+        let clash = vec![
+            tree_parameter("/app/prod/db/host", "String", "a", 1),
+            tree_parameter("/app/prod/db_host", "String", "b", 1),
+        ];
+        assert!(k8s_manifests(ExportFormat::K8sConfigMap, "/app/prod", &clash, &options).is_err());
+    }
+
+    #[test]
+    fn export_files_to_directory() {
+        let dir = std::env::temp_dir().join(format!("ssm_helper_export_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let parameters = vec![
+            tree_parameter("/app/prod/api_key", "SecureString", "k3y", 2),
+            tree_parameter("/app/prod/db/host", "String", "db.internal", 3),
+            tree_parameter("/app/prod/db/pass", "SecureString", "s3cr3t", 7),
+        ];
+
+        let export = export_files("/app/prod", &parameters, &dir, true).unwrap();
+        assert_eq!(export.written, vec!["api_key", "db/host", "db/pass"]);
+        assert!(export.removed.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("db/pass")).unwrap(), "s3cr3t");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join("db/pass"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let manifest = fs::read_to_string(dir.join(CHECKSUM_MANIFEST)).unwrap();
+        assert_eq!(manifest.lines().count(), 3);
+        assert!(manifest.contains(
+            "4e738ca5563c06cfd0018299933d58db1dd8bf97f6973dc99bf6cdc64b5550bd  db/pass\n"
+        ));
+
+        // Unchanged files are left alone, stale ones and empty directories go, files the
+        // export didn't write stay
+        fs::write(dir.join("notes.txt"), "mine").unwrap();
+        let export = export_files("/app/prod", &parameters[..1], &dir, false).unwrap();
+        assert!(export.written.is_empty());
+        assert_eq!(
+            export.removed,
+            vec![
+                CHECKSUM_MANIFEST.to_string(),
+                "db/host".to_string(),
+                "db/pass".to_string()
+            ]
+        );
+        assert!(!dir.join("db").exists());
+        assert_eq!(fs::read_to_string(dir.join("api_key")).unwrap(), "k3y");
+        assert_eq!(fs::read_to_string(dir.join("notes.txt")).unwrap(), "mine");
+        assert_eq!(
+            fs::read_to_string(dir.join(EXPORT_STATE_FILE)).unwrap(),
+            "api_key\n"
+        );
+
+        // A directory an export didn't create is refused
+        fs::remove_file(dir.join(EXPORT_STATE_FILE)).unwrap();
+        assert!(export_files("/app/prod", &parameters, &dir, false).is_err());
+        assert!(!dir.join("db").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn export_variables() {
+        let parameters = vec![
+            tree_parameter("/app/prod/db/host", "String", "db.${env}", 3),
+            tree_parameter("/app/prod/db/pass", "SecureString", "s3\"cr3t", 7),
+            tree_parameter("/app/prod/1st-subnets", "StringList", "a,b", 1),
+        ];
+        let output = |format| variables_output(format, "/app/prod", &parameters).unwrap();
+
+        assert_eq!(
+            output(ExportFormat::Tfvars),
+            "_1st-subnets = [\"a\", \"b\"]\ndb_host = \"db.$${env}\"\ndb_pass = \"s3\\\"cr3t\"\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&output(ExportFormat::TfvarsJson)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "_1st-subnets": ["a", "b"], "db_host": "db.${env}", "db_pass": "s3\"cr3t" })
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&output(ExportFormat::CfnParams)).unwrap();
+        assert_eq!(
+            json[0],
+            serde_json::json!({ "ParameterKey": "1stSubnets", "ParameterValue": "a,b" })
+        );
+        assert_eq!(json[1]["ParameterKey"], "DbHost");
+        assert_eq!(
+            output(ExportFormat::SamOverrides),
+            "1stSubnets=\"a,b\" DbHost=\"db.${env}\" DbPass=\"s3\\\"cr3t\"\n"
+        );
+
+        let variables = terraform_variables("/app/prod", &parameters).unwrap();
+        assert!(variables.contains("variable \"_1st-subnets\" {\n  description = \"/app/prod/1st-subnets\"\n  type        = list(string)\n}\n"));
+        assert!(variables.contains("variable \"db_pass\" {\n  description = \"/app/prod/db/pass\"\n  type        = string\n  sensitive   = true\n}\n"));
+        assert!(!variables.contains("s3"));
+    }
 }
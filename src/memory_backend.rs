@@ -0,0 +1,510 @@
+//! In-memory Parameter Store used by the tests: an `SsmClient` dispatcher that
+//! answers the SSM JSON protocol from a map instead of going over the wire.
+//! STS GetCallerIdentity answers with the backend's account.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{self, FutureResult};
+use http::{HeaderMap, StatusCode};
+use rusoto_core::request::HttpResponse;
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, Client, DispatchSignedRequest, HttpDispatchError, Region};
+use rusoto_credential::StaticProvider;
+use serde_json::value::Value as Json;
+
+pub const DEFAULT_KEY_ID: &str = "alias/aws/ssm";
+pub const DEFAULT_ACCOUNT: &str = "123456789012";
+const SECRETS_MANAGER_PREFIX: &str = "/aws/reference/secretsmanager/";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoredParameter {
+    pub name: String,
+    pub p_type: String,
+    pub value: String,
+    pub version: i64,
+    pub key_id: Option<String>,
+    pub description: Option<String>,
+    pub tier: Option<String>,
+    pub allowed_pattern: Option<String>,
+    pub policies: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    /// Label => version
+    pub labels: BTreeMap<String, i64>,
+    /// Version => value, of the versions written through PutParameter
+    pub history: BTreeMap<i64, String>,
+}
+
+impl StoredParameter {
+    /// Value of a version, the current one always resolves
+    fn value_at(&self, version: i64) -> Option<&String> {
+        if version == self.version {
+            Some(&self.value)
+        } else {
+            self.history.get(&version)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    store: Arc<Mutex<BTreeMap<String, StoredParameter>>>,
+    /// DEFAULT_ACCOUNT if not set
+    account: Option<String>,
+    /// Regions (endpoints) the STS calls were sent to
+    sts_regions: Arc<Mutex<Vec<Region>>>,
+}
+
+type Response = Result<Json, (&'static str, String)>;
+
+impl MemoryBackend {
+    /// Another account's Parameter Store
+    pub fn with_account(account: &str) -> Self {
+        MemoryBackend {
+            account: Some(account.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn sts_regions(&self) -> Vec<Region> {
+        self.sts_regions.lock().unwrap().clone()
+    }
+
+    fn caller_identity(&self) -> HttpResponse {
+        let account = self.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+        let body = format!(
+            r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <GetCallerIdentityResult>
+    <Arn>arn:aws:iam::{0}:user/test</Arn>
+    <UserId>AIDTEST</UserId>
+    <Account>{0}</Account>
+  </GetCallerIdentityResult>
+  <ResponseMetadata>
+    <RequestId>00000000-0000-0000-0000-000000000000</RequestId>
+  </ResponseMetadata>
+</GetCallerIdentityResponse>
+"#,
+            account
+        );
+        HttpResponse {
+            status: StatusCode::OK,
+            body: ByteStream::from(body.into_bytes()),
+            headers: HeaderMap::default(),
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        Client::new_with(
+            StaticProvider::new_minimal("test".to_string(), "test".to_string()),
+            self.clone(),
+        )
+    }
+
+    pub fn insert(&self, parameter: StoredParameter) {
+        let mut store = self.store.lock().unwrap();
+        store.insert(parameter.name.clone(), parameter);
+    }
+
+    pub fn get(&self, name: &str) -> Option<StoredParameter> {
+        self.store.lock().unwrap().get(name).cloned()
+    }
+
+    /// What a GetParameter without decryption returns for a SecureString
+    pub fn ciphertext(key_id: &str, value: &str) -> String {
+        let hex: String = value.bytes().map(|b| format!("{:02x}", b)).collect();
+        format!("AQICAH{}:{}", key_id.len(), hex)
+    }
+
+    fn handle(&self, action: &str, body: &Json) -> Response {
+        let mut store = self.store.lock().unwrap();
+        let s = |key: &str| body[key].as_str().map(String::from);
+        match action {
+            "PutParameter" => {
+                let name = s("Name").unwrap_or_default();
+                let overwrite = body["Overwrite"].as_bool().unwrap_or(false);
+                let tags = tag_map(&body["Tags"]);
+                if overwrite && !tags.is_empty() {
+                    return Err((
+                        "ValidationException",
+                        "Tags can't be set on Overwrite".to_string(),
+                    ));
+                }
+                let p_type = s("Type").unwrap_or_default();
+                let previous = store.get(&name).cloned();
+                if previous.is_some() && !overwrite {
+                    return Err(("ParameterAlreadyExists", name));
+                }
+                let mut previous = previous.unwrap_or_default();
+                let value = s("Value").unwrap_or_default();
+                previous.history.insert(previous.version + 1, value.clone());
+                let key_id = match p_type.as_str() {
+                    "SecureString" => s("KeyId").or_else(|| Some(DEFAULT_KEY_ID.to_string())),
+                    _ => None,
+                };
+                let parameter = StoredParameter {
+                    name: name.clone(),
+                    value,
+                    version: previous.version + 1,
+                    key_id,
+                    description: s("Description").or(previous.description),
+                    tier: s("Tier").or(previous.tier),
+                    allowed_pattern: s("AllowedPattern").or(previous.allowed_pattern),
+                    policies: s("Policies").or(previous.policies),
+                    tags: if overwrite { previous.tags } else { tags },
+                    labels: previous.labels,
+                    history: previous.history,
+                    p_type,
+                };
+                let tier = parameter
+                    .tier
+                    .clone()
+                    .unwrap_or_else(|| "Standard".to_string());
+                if tier == "Standard" && parameter.value.len() > 4096 {
+                    return Err((
+                        "ValidationException",
+                        "Standard tier parameters support a maximum value of 4096 bytes"
+                            .to_string(),
+                    ));
+                }
+                let version = parameter.version;
+                store.insert(name, parameter);
+                Ok(serde_json::json!({ "Version": version, "Tier": tier }))
+            }
+            "GetParameter" => {
+                let name = s("Name").unwrap_or_default();
+                let decrypt = body["WithDecryption"].as_bool().unwrap_or(false);
+                if name.starts_with(SECRETS_MANAGER_PREFIX) && !decrypt {
+                    return Err((
+                        "ValidationException",
+                        "WithDecryption flag must be True for retrieving a Secret Manager secret."
+                            .to_string(),
+                    ));
+                }
+                match select(&store, &name) {
+                    Some(selected) => {
+                        Ok(serde_json::json!({ "Parameter": selected.json(decrypt) }))
+                    }
+                    None => Err(("ParameterNotFound", name)),
+                }
+            }
+            "GetParameters" => {
+                let decrypt = body["WithDecryption"].as_bool().unwrap_or(false);
+                if let Some(reference) = strings(&body["Names"])
+                    .into_iter()
+                    .find(|n| n.starts_with(SECRETS_MANAGER_PREFIX))
+                {
+                    return Err((
+                        "ValidationException",
+                        format!("{}: GetParameter only", reference),
+                    ));
+                }
+                let (found, invalid): (Vec<String>, Vec<String>) = strings(&body["Names"])
+                    .into_iter()
+                    .partition(|n| select(&store, n).is_some());
+                let parameters: Vec<Json> = found
+                    .iter()
+                    .filter_map(|n| select(&store, n))
+                    .map(|selected| selected.json(decrypt))
+                    .collect();
+                Ok(serde_json::json!({ "Parameters": parameters, "InvalidParameters": invalid }))
+            }
+            "GetParametersByPath" => {
+                let path = s("Path").unwrap_or_default();
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                let recursive = body["Recursive"].as_bool().unwrap_or(false);
+                let decrypt = body["WithDecryption"].as_bool().unwrap_or(false);
+                let parameters: Vec<Json> = store
+                    .values()
+                    .filter(|p| match p.name.strip_prefix(&prefix) {
+                        Some(rest) => recursive || !rest.contains('/'),
+                        None => false,
+                    })
+                    .map(|p| parameter_json(p, decrypt))
+                    .collect();
+                Ok(serde_json::json!({ "Parameters": parameters }))
+            }
+            "DescribeParameters" => {
+                let filters = body["ParameterFilters"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default();
+                let parameters: Vec<Json> = store
+                    .values()
+                    .filter(|p| filters.iter().all(|f| filter_matches(f, p)))
+                    .map(metadata_json)
+                    .collect();
+                Ok(serde_json::json!({ "Parameters": parameters }))
+            }
+            "DeleteParameters" => {
+                let (deleted, invalid): (Vec<String>, Vec<String>) = strings(&body["Names"])
+                    .into_iter()
+                    .partition(|n| store.remove(n).is_some());
+                Ok(
+                    serde_json::json!({ "DeletedParameters": deleted, "InvalidParameters": invalid }),
+                )
+            }
+            "LabelParameterVersion" | "UnlabelParameterVersion" => {
+                let name = s("Name").unwrap_or_default();
+                let parameter = match store.get_mut(&name) {
+                    Some(p) => p,
+                    None => return Err(("ParameterNotFound", name)),
+                };
+                let version = body["ParameterVersion"]
+                    .as_i64()
+                    .unwrap_or(parameter.version);
+                if parameter.value_at(version).is_none() {
+                    return Err(("ParameterVersionNotFound", format!("{}:{}", name, version)));
+                }
+                let labels = strings(&body["Labels"]);
+                if action == "UnlabelParameterVersion" {
+                    let (removed, invalid): (Vec<String>, Vec<String>) =
+                        labels.into_iter().partition(|l| {
+                            parameter.labels.get(l) == Some(&version)
+                                && parameter.labels.remove(l).is_some()
+                        });
+                    return Ok(
+                        serde_json::json!({ "RemovedLabels": removed, "InvalidLabels": invalid }),
+                    );
+                }
+                let (valid, invalid): (Vec<String>, Vec<String>) =
+                    labels.into_iter().partition(|l| valid_label(l));
+                valid.into_iter().for_each(|l| {
+                    parameter.labels.insert(l, version);
+                });
+                Ok(serde_json::json!({ "InvalidLabels": invalid }))
+            }
+            "ListTagsForResource" | "AddTagsToResource" | "RemoveTagsFromResource" => {
+                let name = s("ResourceId").unwrap_or_default();
+                let parameter = match store.get_mut(&name) {
+                    Some(p) => p,
+                    None => return Err(("InvalidResourceId", name)),
+                };
+                match action {
+                    "AddTagsToResource" => parameter.tags.extend(tag_map(&body["Tags"])),
+                    "RemoveTagsFromResource" => strings(&body["TagKeys"]).iter().for_each(|k| {
+                        parameter.tags.remove(k);
+                    }),
+                    _ => {}
+                }
+                let tags: Vec<Json> = parameter
+                    .tags
+                    .iter()
+                    .map(|(k, v)| serde_json::json!({ "Key": k, "Value": v }))
+                    .collect();
+                Ok(serde_json::json!({ "TagList": tags }))
+            }
+            _ => Err(("InvalidAction", action.to_string())),
+        }
+    }
+}
+
+impl DispatchSignedRequest for MemoryBackend {
+    type Future = FutureResult<HttpResponse, HttpDispatchError>;
+
+    fn dispatch(&self, request: SignedRequest, _timeout: Option<Duration>) -> Self::Future {
+        if request.service == "sts" {
+            self.sts_regions
+                .lock()
+                .unwrap()
+                .push(request.region.clone());
+            return future::ok(self.caller_identity());
+        }
+        let action = request
+            .headers
+            .get("x-amz-target")
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).to_string())
+            .unwrap_or_default();
+        let action = action.trim_start_matches("AmazonSSM.");
+        let body: Json = match &request.payload {
+            Some(SignedRequestPayload::Buffer(bytes)) => {
+                serde_json::from_slice(bytes).unwrap_or(Json::Null)
+            }
+            _ => Json::Null,
+        };
+
+        let (status, body) = match self.handle(action, &body) {
+            Ok(json) => (StatusCode::OK, json),
+            Err((error, message)) => (
+                StatusCode::BAD_REQUEST,
+                serde_json::json!({ "__type": error, "message": message }),
+            ),
+        };
+        future::ok(HttpResponse {
+            status,
+            body: ByteStream::from(body.to_string().into_bytes()),
+            headers: HeaderMap::default(),
+        })
+    }
+}
+
+fn strings(json: &Json) -> Vec<String> {
+    json.as_array()
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn tag_map(json: &Json) -> BTreeMap<String, String> {
+    json.as_array()
+        .map(|a| {
+            a.iter()
+                .map(|t| {
+                    let v = |k: &str| t[k].as_str().unwrap_or_default().to_string();
+                    (v("Key"), v("Value"))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn filter_matches(filter: &Json, p: &StoredParameter) -> bool {
+    let values = strings(&filter["Values"]);
+    if filter["Key"] == "Path" {
+        let recursive = filter["Option"] == "Recursive";
+        return values.iter().any(|path| {
+            let prefix = format!("{}/", path.trim_end_matches('/'));
+            match p.name.strip_prefix(&prefix) {
+                Some(rest) => recursive || !rest.contains('/'),
+                None => false,
+            }
+        });
+    }
+    let field = match filter["Key"].as_str().unwrap_or_default() {
+        "Name" => p.name.clone(),
+        "Type" => p.p_type.clone(),
+        "Tier" => p.tier.clone().unwrap_or_else(|| "Standard".to_string()),
+        "KeyId" => p.key_id.clone().unwrap_or_default(),
+        key if key.starts_with("tag:") => match p.tags.get(&key[4..]) {
+            Some(v) => v.clone(),
+            None => return false,
+        },
+        _ => return true,
+    };
+    match filter["Option"].as_str().unwrap_or("Equals") {
+        "BeginsWith" => values.iter().any(|v| field.starts_with(v.as_str())),
+        "Contains" => values.iter().any(|v| field.contains(v.as_str())),
+        _ => values.iter().any(|v| v == &field),
+    }
+}
+
+/// Labels can't start with a digit, aws or ssm
+fn valid_label(label: &str) -> bool {
+    let lower = label.to_lowercase();
+    !label.is_empty()
+        && !label.starts_with(|c: char| c.is_ascii_digit())
+        && !lower.starts_with("aws")
+        && !lower.starts_with("ssm")
+}
+
+/// A parameter at the version a `name[:version|:label]` selector points to
+struct Selected<'a> {
+    parameter: &'a StoredParameter,
+    selector: Option<String>,
+    version: i64,
+    value: &'a String,
+}
+
+fn select<'a>(store: &'a BTreeMap<String, StoredParameter>, name: &str) -> Option<Selected<'a>> {
+    let (name, selector) = match name.rfind(':') {
+        Some(pos) => (&name[..pos], Some(&name[pos + 1..])),
+        None => (name, None),
+    };
+    let parameter = store.get(name)?;
+    let version = match selector {
+        None => parameter.version,
+        Some(s) => match s.parse::<i64>() {
+            Ok(version) => version,
+            Err(_) => *parameter.labels.get(s)?,
+        },
+    };
+    Some(Selected {
+        parameter,
+        selector: selector.map(|s| format!(":{}", s)),
+        version,
+        value: parameter.value_at(version)?,
+    })
+}
+
+impl<'a> Selected<'a> {
+    fn json(&self, decrypt: bool) -> Json {
+        let p = self.parameter;
+        let value = match (&p.key_id, decrypt) {
+            (Some(key_id), false) => MemoryBackend::ciphertext(key_id, self.value),
+            _ => self.value.clone(),
+        };
+        let mut json = serde_json::json!({
+            "Name": p.name,
+            "Type": p.p_type,
+            "Value": value,
+            "Version": self.version,
+            "Selector": self.selector,
+            "ARN": format!("arn:aws:ssm:us-east-1:123456789012:parameter{}", p.name),
+            "LastModifiedDate": 1_577_836_800.0,
+        });
+        // References pass the GetSecretValue response on, its VersionId is the version here
+        if let Some(secret) = p.name.strip_prefix(SECRETS_MANAGER_PREFIX) {
+            let mut stages: Vec<&str> = p
+                .labels
+                .iter()
+                .filter(|(_, v)| **v == self.version)
+                .map(|(label, _)| label.as_str())
+                .collect();
+            if self.version == p.version {
+                stages.insert(0, "AWSCURRENT");
+            }
+            json["SourceResult"] = Json::from(
+                serde_json::json!({
+                    "Name": secret,
+                    "VersionId": self.version.to_string(),
+                    "VersionStages": stages,
+                })
+                .to_string(),
+            );
+        }
+        json
+    }
+}
+
+fn parameter_json(p: &StoredParameter, decrypt: bool) -> Json {
+    Selected {
+        parameter: p,
+        selector: None,
+        version: p.version,
+        value: &p.value,
+    }
+    .json(decrypt)
+}
+
+fn metadata_json(p: &StoredParameter) -> Json {
+    let policies: Vec<Json> = p
+        .policies
+        .as_ref()
+        .and_then(|text| serde_json::from_str::<Vec<Json>>(text).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|policy| {
+            serde_json::json!({
+                "PolicyText": policy.to_string(),
+                "PolicyType": policy["Type"],
+                "PolicyStatus": "Pending",
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "Name": p.name,
+        "Type": p.p_type,
+        "Version": p.version,
+        "KeyId": p.key_id,
+        "Description": p.description,
+        "Tier": p.tier.clone().unwrap_or_else(|| "Standard".to_string()),
+        "AllowedPattern": p.allowed_pattern,
+        "Policies": policies,
+        "LastModifiedDate": 1_577_836_800.0,
+        "LastModifiedUser": "arn:aws:iam::123456789012:user/test",
+    })
+}
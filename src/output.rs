@@ -0,0 +1,181 @@
+use serde::Serialize;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use crate::plan::{render_table, PlanFormat, PlannedOperation};
+use crate::ssm_parameters::SSMParameter;
+
+/// Parameter output, one JSON document per line on STDOUT
+pub fn print_parameters<T, I>(parameters: I)
+where
+    T: Serialize,
+    I: IntoIterator<Item = T>,
+{
+    parameters
+        .into_iter()
+        .for_each(|p| println!("{}", serde_json::to_string(&p).unwrap()));
+}
+
+/// Parameter names only, one per line on STDOUT
+pub fn print_names<I>(parameters: I)
+where
+    I: IntoIterator<Item = SSMParameter>,
+{
+    parameters
+        .into_iter()
+        .filter_map(|p| p.name)
+        .for_each(|n| println!("{}", n));
+}
+
+/// --dry-run plan on STDOUT, an aligned table or one JSON document per operation
+pub fn print_plan(operations: &[PlannedOperation], format: PlanFormat) {
+    match format {
+        PlanFormat::Table if !operations.is_empty() => println!("{}", render_table(operations)),
+        PlanFormat::Table => {}
+        PlanFormat::Json => print_parameters(operations),
+    }
+}
+
+/// Ask on STDERR (STDOUT stays parseable), true only for y / yes
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Typed confirmation on STDERR, only from a terminal: true when `expected` is typed back
+pub fn confirm_typed(prompt: &str, expected: &str) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        warn!("{} No terminal to confirm it.", prompt);
+        return Ok(false);
+    }
+    eprint!("{}\nType '{}' to continue: ", prompt, expected);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim() == expected)
+}
+
+/// Permissions `path` ends up with
+#[derive(Clone, Copy)]
+enum FileMode {
+    /// An existing file's, else the umask's (as `fs::write`)
+    Keep,
+    /// An existing file's, else only readable by its owner (0600)
+    KeepOrPrivate,
+    /// These, whatever the file had before
+    Set(u32),
+}
+
+/// Write to a temporary file next to `path`, then rename it over `path`:
+/// readers see the old or the new contents, never a partial file. An existing file keeps
+/// its permissions (and owner where allowed), a new one follows the umask.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_file_atomically(path, contents, FileMode::Keep)
+}
+
+/// `write_atomically`, but a new file is only readable by its owner (0600)
+pub fn write_atomically_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_file_atomically(path, contents, FileMode::KeepOrPrivate)
+}
+
+/// `write_atomically`, `path` ends up with `mode` (ex: 0o600) whatever it had before
+pub fn write_atomically_with_mode(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    write_file_atomically(path, contents, FileMode::Set(mode))
+}
+
+fn write_file_atomically(path: &Path, contents: &[u8], mode: FileMode) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file path"))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let existing = fs::metadata(path).ok();
+    // A leftover temporary file would keep its permissions through open
+    let _ = fs::remove_file(&tmp);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if !matches!(mode, FileMode::Keep) {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = (mode, &existing);
+    let written = options.open(&tmp).and_then(|mut file| {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            let keep = existing.as_ref().map(|m| m.mode() & 0o7777);
+            if let (FileMode::Keep | FileMode::KeepOrPrivate, Some(existing)) = (mode, &existing) {
+                // Only root may give a file away, the others keep it as their own
+                let _ =
+                    std::os::unix::fs::fchown(&file, Some(existing.uid()), Some(existing.gid()));
+            }
+            // After the chown, which clears setuid / setgid
+            let set = match mode {
+                FileMode::Set(mode) => Some(mode),
+                FileMode::KeepOrPrivate => keep.or(Some(0o600)),
+                FileMode::Keep => keep,
+            };
+            if let Some(set) = set {
+                file.set_permissions(fs::Permissions::from_mode(set))?;
+            }
+        }
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_file_follows_the_umask_unless_private() {
+        let dir = std::env::temp_dir().join(format!("ssm_helper_umask_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("plain"), b"").unwrap();
+
+        write_atomically(&dir.join("out"), b"pass=s3cr3t").unwrap();
+        assert_eq!(mode(&dir.join("out")), mode(&dir.join("plain")));
+        write_atomically_private(&dir.join("private"), b"pass=s3cr3t").unwrap();
+        assert_eq!(mode(&dir.join("private")), 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_file_keeps_its_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("ssm_helper_keep_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out");
+        fs::write(&out, b"pass=s3cr3t").unwrap();
+        fs::set_permissions(&out, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomically_private(&out, b"pass=again").unwrap();
+        assert_eq!(mode(&out), 0o640);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "pass=again");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlannedAction {
+    Create,
+    Overwrite,
+    Delete,
+    Tag,
+    Untag,
+    Label,
+    Unlabel,
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self {
+            PlannedAction::Create => "create",
+            PlannedAction::Overwrite => "overwrite",
+            PlannedAction::Delete => "delete",
+            PlannedAction::Tag => "tag",
+            PlannedAction::Untag => "untag",
+            PlannedAction::Label => "label",
+            PlannedAction::Unlabel => "unlabel",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+/// A write --dry-run stopped, values are always masked
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlannedOperation {
+    pub action: PlannedAction,
+    pub name: String,
+    pub p_type: Option<String>,
+    pub value: Option<String>,
+    pub from_version: Option<i64>,
+    pub to_version: Option<i64>,
+    /// Tag keys / labels involved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl PlannedOperation {
+    fn version(&self) -> String {
+        let v = |v: Option<i64>| v.map_or("-".to_string(), |v| format!("v{}", v));
+        match (self.from_version, self.to_version) {
+            (None, None) => String::new(),
+            (from, to) => format!("{} => {}", v(from), v(to)),
+        }
+    }
+}
+
+/// Collects the writes of an SSMOps in --dry-run mode instead of sending them
+#[derive(Debug, Default)]
+pub struct Planner {
+    operations: RefCell<Vec<PlannedOperation>>,
+}
+
+impl Planner {
+    pub fn record(&self, operation: PlannedOperation) {
+        debug!("Planned: {} {}", operation.action, operation.name);
+        self.operations.borrow_mut().push(operation);
+    }
+
+    pub fn operations(&self) -> Vec<PlannedOperation> {
+        self.operations.borrow().clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for PlanFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(PlanFormat::Table),
+            "json" => Ok(PlanFormat::Json),
+            _ => Err(format!("Invalid Plan Format: {} (table, json)", s)),
+        }
+    }
+}
+
+/// Aligned ACTION NAME TYPE VALUE VERSION table
+pub fn render_table(operations: &[PlannedOperation]) -> String {
+    let rows: Vec<[String; 5]> = operations
+        .iter()
+        .map(|op| {
+            [
+                op.action.to_string(),
+                op.name.clone(),
+                op.p_type.clone().unwrap_or_default(),
+                op.value
+                    .clone()
+                    .or_else(|| op.detail.clone())
+                    .unwrap_or_default(),
+                op.version(),
+            ]
+        })
+        .collect();
+    let header = [
+        "ACTION".to_string(),
+        "NAME".to_string(),
+        "TYPE".to_string(),
+        "VALUE".to_string(),
+        "VERSION".to_string(),
+    ];
+    let mut widths = [0; 5];
+    for row in rows.iter().chain(std::iter::once(&header)) {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| {
+            row.iter()
+                .zip(widths.iter())
+                .map(|(cell, w)| format!("{:<width$}", cell, width = w))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde_json::value::Value as Json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyUnit {
+    Days,
+    Hours,
+}
+
+/// Policy attribute duration, ex: 15d, 12h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolicyDuration {
+    pub amount: u32,
+    pub unit: PolicyUnit,
+}
+
+impl PolicyDuration {
+    fn duration(&self) -> Duration {
+        match self.unit {
+            PolicyUnit::Days => Duration::days(i64::from(self.amount)),
+            PolicyUnit::Hours => Duration::hours(i64::from(self.amount)),
+        }
+    }
+
+    fn unit_name(&self) -> &'static str {
+        match self.unit {
+            PolicyUnit::Days => "Days",
+            PolicyUnit::Hours => "Hours",
+        }
+    }
+}
+
+impl FromStr for PolicyDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (amount, unit) = match s.char_indices().last() {
+            Some((pos, 'd')) | Some((pos, 'D')) => (&s[..pos], PolicyUnit::Days),
+            Some((pos, 'h')) | Some((pos, 'H')) => (&s[..pos], PolicyUnit::Hours),
+            _ => return Err(format!("Invalid Duration, expected <n>d or <n>h: {}", s)),
+        };
+        match amount.parse::<u32>() {
+            Ok(amount) if amount > 0 => Ok(PolicyDuration { amount, unit }),
+            _ => Err(format!("Invalid Duration, expected <n>d or <n>h: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for PolicyDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.amount, self.unit_name().to_lowercase())
+    }
+}
+
+/// Expiration, ExpirationNotification and NoChangeNotification policies (Advanced tier only)
+#[derive(Debug, Default)]
+pub struct SSMParameterPolicies {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub notify_before_expiry: Option<PolicyDuration>,
+    pub notify_no_change_after: Option<PolicyDuration>,
+}
+
+impl SSMParameterPolicies {
+    /// JSON array PutParameter expects, None without policies, validated against `now`
+    pub fn to_json(&self, now: DateTime<Utc>) -> Result<Option<String>, String> {
+        let mut policies: Vec<Json> = Vec::new();
+        if let Some(expires_at) = self.expires_at {
+            if expires_at <= now {
+                return Err(format!("Expiration must be in the future: {}", expires_at));
+            }
+            policies.push(policy(
+                "Expiration",
+                serde_json::json!({
+                    "Timestamp": expires_at.to_rfc3339_opts(SecondsFormat::Millis, true)
+                }),
+            ));
+        }
+        if let Some(before) = self.notify_before_expiry {
+            match self.expires_at {
+                None => return Err("Expiration notification requires an expiration".to_string()),
+                Some(expires_at) if expires_at - before.duration() <= now => {
+                    return Err(format!(
+                        "Expiration notification {} before {} would be in the past",
+                        before, expires_at
+                    ));
+                }
+                _ => {}
+            }
+            policies.push(policy(
+                "ExpirationNotification",
+                serde_json::json!({
+                    "Before": before.amount.to_string(),
+                    "Unit": before.unit_name()
+                }),
+            ));
+        }
+        if let Some(after) = self.notify_no_change_after {
+            policies.push(policy(
+                "NoChangeNotification",
+                serde_json::json!({
+                    "After": after.amount.to_string(),
+                    "Unit": after.unit_name()
+                }),
+            ));
+        }
+
+        if policies.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Json::Array(policies).to_string()))
+        }
+    }
+}
+
+fn policy(p_type: &str, attributes: Json) -> Json {
+    serde_json::json!({ "Type": p_type, "Version": "1.0", "Attributes": attributes })
+}
+
+/// Human readable policy, ex: "Notify 15 days before expiration", None if not understood
+pub fn policy_summary(text: &str) -> Option<String> {
+    let policy: Json = serde_json::from_str(text).ok()?;
+    let attribute = |key: &str| policy["Attributes"][key].as_str();
+    let duration = |key: &str| -> Option<String> {
+        Some(format!(
+            "{} {}",
+            attribute(key)?,
+            attribute("Unit")?.to_lowercase()
+        ))
+    };
+    match policy["Type"].as_str()? {
+        "Expiration" => Some(format!("Expires at {}", attribute("Timestamp")?)),
+        "ExpirationNotification" => {
+            Some(format!("Notify {} before expiration", duration("Before")?))
+        }
+        "NoChangeNotification" => Some(format!("Notify if unchanged for {}", duration("After")?)),
+        _ => None,
+    }
+}
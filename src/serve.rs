@@ -0,0 +1,724 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use serde_json::value::Value as Json;
+
+use crate::ssm_ops::SSMOps;
+use crate::ssm_parameters::{SSMParameter, SSMParametersByPathRequest, SECRETS_MANAGER_PREFIX};
+
+/// Environment variable holding the bearer token `serve` requires, no auth if not set
+pub const SERVE_TOKEN_ENV: &str = "SSM_HELPER_SERVE_TOKEN";
+/// Header the AWS Parameters and Secrets Lambda extension clients send their token in
+pub const EXTENSION_TOKEN_HEADER: &str = "x-aws-parameters-secrets-token";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: Json) -> Self {
+        Response {
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response::json(status, serde_json::json!({ "message": message }))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Bad Gateway",
+        }
+    }
+}
+
+enum Slot {
+    Pending,
+    Ready(Instant, Response),
+}
+
+/// TTL cache of successful responses, concurrent misses on a key wait for a single fetch
+pub struct Cache {
+    ttl: Duration,
+    slots: Mutex<HashMap<String, Slot>>,
+    fetched: Condvar,
+}
+
+impl Cache {
+    pub fn new(ttl: Duration) -> Self {
+        Cache {
+            ttl,
+            slots: Mutex::new(HashMap::new()),
+            fetched: Condvar::new(),
+        }
+    }
+
+    pub fn get_or_fetch<F>(&self, key: &str, fetch: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        let mut slots = self.slots.lock().unwrap();
+        let mut waited = false;
+        loop {
+            match slots.get(key) {
+                // A waiter takes the fetch it waited for, however short the TTL
+                Some(Slot::Ready(at, response)) if waited || at.elapsed() < self.ttl => {
+                    trace!("Cache hit: {}", key);
+                    return response.clone();
+                }
+                Some(Slot::Pending) => {
+                    slots = self.fetched.wait(slots).unwrap();
+                    waited = true;
+                }
+                _ => break,
+            }
+        }
+        slots.insert(key.to_string(), Slot::Pending);
+        drop(slots);
+
+        let response = fetch();
+        let mut slots = self.slots.lock().unwrap();
+        if response.status == 200 {
+            slots.insert(
+                key.to_string(),
+                Slot::Ready(Instant::now(), response.clone()),
+            );
+        } else {
+            slots.remove(key);
+        }
+        self.fetched.notify_all();
+        response
+    }
+}
+
+/// Lambda extension compatible local API:
+/// GET /systemsmanager/parameters/get?name=NAME[&version=N|&label=L][&withDecryption=true]
+/// GET /systemsmanager/parameters/get-by-path?path=PATH[&recursive=true][&withDecryption=true]
+/// GET /secretsmanager/get?secretId=ID[&versionId=V|&versionStage=S] (through the Parameter Store reference)
+pub struct Server {
+    ssm: Mutex<SSMOps>,
+    cache: Cache,
+    token: Option<String>,
+}
+
+impl Server {
+    pub fn new(ssm: &SSMOps, ttl: Duration, token: Option<String>) -> Self {
+        Server {
+            ssm: Mutex::new(ssm.fork()),
+            cache: Cache::new(ttl),
+            token: token.filter(|t| !t.is_empty()),
+        }
+    }
+
+    /// `target` is the request path and query, header names are lowercase
+    pub fn handle(
+        &self,
+        method: &str,
+        target: &str,
+        headers: &HashMap<String, String>,
+    ) -> Response {
+        if method != "GET" {
+            return Response::error(405, "Only GET is supported");
+        }
+        if let Some(token) = &self.token {
+            let bearer = headers
+                .get("authorization")
+                .and_then(|v| v.strip_prefix("Bearer "));
+            let sent = bearer.or_else(|| headers.get(EXTENSION_TOKEN_HEADER).map(String::as_str));
+            if sent != Some(token.as_str()) {
+                return Response::error(401, "Missing or invalid token");
+            }
+        }
+        let (route, query) = match target.find('?') {
+            Some(pos) => (&target[..pos], parse_query(&target[pos + 1..])),
+            None => (target, HashMap::new()),
+        };
+        let flag = |key: &str| query.get(key).is_some_and(|v| v == "true");
+        let decrypt = flag("withDecryption");
+
+        match route {
+            "/systemsmanager/parameters/get" => {
+                let name = match query.get("name") {
+                    Some(name) => name,
+                    None => return Response::error(400, "name is required"),
+                };
+                let name = match (query.get("version"), query.get("label")) {
+                    (Some(_), Some(_)) => {
+                        return Response::error(400, "version and label are exclusive")
+                    }
+                    (Some(selector), None) | (None, Some(selector)) => {
+                        format!("{}:{}", name, selector)
+                    }
+                    (None, None) => name.to_string(),
+                };
+                self.cached(&format!("get {} {}", name, decrypt), |ssm| {
+                    match ssm.read_one(&name, decrypt)? {
+                        Some(p) => Ok(Response::json(
+                            200,
+                            serde_json::json!({ "Parameter": parameter_json(&p), "ResultMetadata": {} }),
+                        )),
+                        None => Ok(Response::error(404, &format!("Parameter not found: {}", name))),
+                    }
+                })
+            }
+            "/systemsmanager/parameters/get-by-path" => {
+                let path = match query.get("path") {
+                    Some(path) => path.to_string(),
+                    None => return Response::error(400, "path is required"),
+                };
+                let recursive = flag("recursive");
+                self.cached(&format!("path {} {} {}", path, recursive, decrypt), |ssm| {
+                    let parameters = ssm
+                        .get_parameters_by_path(&SSMParametersByPathRequest {
+                            path: path.clone(),
+                            recursive: Some(recursive),
+                            with_decryption: Some(decrypt),
+                            parameter_filters: vec![],
+                        })
+                        .map_err(|e| format_err!("{}", e))?
+                        .parameters;
+                    let parameters: Vec<Json> = parameters.iter().map(parameter_json).collect();
+                    Ok(Response::json(
+                        200,
+                        serde_json::json!({ "Parameters": parameters }),
+                    ))
+                })
+            }
+            "/secretsmanager/get" => {
+                let id = match query.get("secretId") {
+                    Some(id) => id.to_string(),
+                    None => return Response::error(400, "secretId is required"),
+                };
+                // The reference takes a version id or staging label as its selector
+                let selector = match (query.get("versionId"), query.get("versionStage")) {
+                    (Some(_), Some(_)) => {
+                        return Response::error(400, "versionId and versionStage are exclusive")
+                    }
+                    (Some(selector), None) | (None, Some(selector)) => format!(":{}", selector),
+                    (None, None) => String::new(),
+                };
+                self.cached(&format!("secret {}{}", id, selector), |ssm| {
+                    let name = format!(
+                        "{}{}{}",
+                        SECRETS_MANAGER_PREFIX,
+                        id.trim_start_matches('/'),
+                        selector
+                    );
+                    match ssm.read_one(&name, true)? {
+                        Some(p) => {
+                            let (version_id, stages) = secret_version(
+                                &p,
+                                query.get("versionId"),
+                                query.get("versionStage"),
+                            );
+                            Ok(Response::json(
+                                200,
+                                serde_json::json!({
+                                    "ARN": p.arn,
+                                    "Name": id,
+                                    "SecretString": p.value,
+                                    "VersionId": version_id,
+                                    "VersionStages": stages,
+                                    "CreatedDate": p.last_modified_date,
+                                }),
+                            ))
+                        }
+                        None => Ok(Response::error(404, &format!("Secret not found: {}", id))),
+                    }
+                })
+            }
+            _ => Response::error(404, &format!("Unknown route: {}", route)),
+        }
+    }
+
+    fn cached<F>(&self, key: &str, fetch: F) -> Response
+    where
+        F: FnOnce(&SSMOps) -> Result<Response, Error>,
+    {
+        self.cache.get_or_fetch(key, || {
+            let ssm = self.ssm.lock().unwrap().fork();
+            fetch(&ssm).unwrap_or_else(|e| {
+                warn!("{}", e);
+                Response::error(502, &e.to_string())
+            })
+        })
+    }
+}
+
+/// VersionId and VersionStages of a secret read through its reference: from the GetSecretValue
+/// response SSM passes on as SourceResult, the requested selector otherwise
+fn secret_version(
+    p: &SSMParameter,
+    version_id: Option<&String>,
+    version_stage: Option<&String>,
+) -> (Json, Json) {
+    let source: Json = p
+        .source_result
+        .as_deref()
+        .and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or_default();
+    let id = match &source["VersionId"] {
+        Json::Null => version_id.map_or(Json::Null, |v| Json::from(v.as_str())),
+        id => id.clone(),
+    };
+    let stages = match &source["VersionStages"] {
+        Json::Null => match (version_id, version_stage) {
+            (_, Some(stage)) => serde_json::json!([stage]),
+            (None, None) => serde_json::json!(["AWSCURRENT"]),
+            (Some(_), None) => serde_json::json!([]),
+        },
+        stages => stages.clone(),
+    };
+    (id, stages)
+}
+
+/// GetParameter JSON shape
+fn parameter_json(p: &SSMParameter) -> Json {
+    serde_json::json!({
+        "ARN": p.arn,
+        "LastModifiedDate": p.last_modified_date,
+        "Name": p.name,
+        "Selector": p.selector,
+        "SourceResult": p.source_result,
+        "Type": p.p_type,
+        "Value": p.value,
+        "Version": p.version,
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(pos) => (
+                percent_decode(&pair[..pos]),
+                percent_decode(&pair[pos + 1..]),
+            ),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// %XX escapes and + as space
+pub fn percent_decode(s: &str) -> String {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let bytes = s.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some([b'%', high, low]) => hex(*high).zip(hex(*low)).map(|(h, l)| h * 16 + l),
+            _ => None,
+        };
+        match (escaped, bytes[i]) {
+            (Some(b), _) => {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+            (None, b'+') => out.push(b' '),
+            (None, b) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// One request per connection: request line and headers, the body is ignored
+fn handle_connection<S: Read + Write>(server: &Server, stream: S) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => bail!("Malformed request line: {:?}", request_line),
+    };
+    let mut headers: HashMap<String, String> = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(pos) = line.find(':') {
+            headers.insert(
+                line[..pos].trim().to_lowercase(),
+                line[pos + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    let response = server.handle(&method, &target, &headers);
+    debug!(
+        "{} {} => {}",
+        method,
+        target.split('?').next().unwrap_or_default(),
+        response.status
+    );
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn spawn_connection<S: Read + Write + Send + 'static>(server: &Arc<Server>, stream: S) {
+    let server = Arc::clone(server);
+    thread::spawn(move || {
+        if let Err(e) = handle_connection(&server, stream) {
+            debug!("Connection: {}", e);
+        }
+    });
+}
+
+pub fn serve_tcp(server: Server, address: &str) -> Result<(), Error> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving on http://{}", listener.local_addr()?);
+    let server = Arc::new(server);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+                spawn_connection(&server, stream);
+            }
+            Err(e) => warn!("Accept: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// The socket is only accessible to its owner (0600): bound in a private (0700) directory next
+/// to `path`, then moved into place. A stale socket is replaced, any other file is refused.
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener, Error> {
+    use std::fs;
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => bail!("{:?} exists and is not a socket", path),
+        Err(_) => {}
+    }
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format_err!("{:?} is not a socket path", path))?;
+    let private = path.with_file_name(format!(
+        ".{}.{}.d",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("socket");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&bound);
+    fs::remove_dir(&private)?;
+    Ok(listener?)
+}
+
+#[cfg(unix)]
+pub fn serve_unix(server: Server, path: &std::path::Path) -> Result<(), Error> {
+    let listener = bind_unix(path)?;
+    info!("Serving on unix:{:?}", path);
+    let server = Arc::new(server);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+                spawn_connection(&server, stream);
+            }
+            Err(e) => warn!("Accept: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk_cache::{CacheMode, DiskCache};
+    use crate::memory_backend::{MemoryBackend, StoredParameter};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn get(server: &Server, target: &str) -> (u16, Json) {
+        let response = server.handle("GET", target, &HashMap::new());
+        (
+            response.status,
+            serde_json::from_str(&response.body).unwrap(),
+        )
+    }
+
+    fn source_backend() -> MemoryBackend {
+        let backend = MemoryBackend::default();
+        backend.insert(StoredParameter {
+            name: "/src/db/pass".to_string(),
+            p_type: "SecureString".to_string(),
+            value: "s3cr3t".to_string(),
+            version: 3,
+            ..Default::default()
+        });
+        backend
+    }
+
+    fn server(backend: &MemoryBackend, token: Option<&str>) -> Server {
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        Server::new(&ssm, Duration::from_secs(300), token.map(String::from))
+    }
+
+    fn secret_backend() -> MemoryBackend {
+        let backend = MemoryBackend::default();
+        backend.insert(StoredParameter {
+            name: "/aws/reference/secretsmanager/api".to_string(),
+            p_type: "SecureString".to_string(),
+            value: "n3w".to_string(),
+            version: 2,
+            labels: vec![("AWSPREVIOUS".to_string(), 1)].into_iter().collect(),
+            history: vec![(1, "0ld".to_string())].into_iter().collect(),
+            ..Default::default()
+        });
+        backend
+    }
+
+    #[test]
+    fn parameter_get_decrypts_a_percent_encoded_name() {
+        let server = server(&source_backend(), None);
+        let (status, body) = get(
+            &server,
+            "/systemsmanager/parameters/get?name=%2Fsrc%2Fdb%2Fpass&withDecryption=true",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["Parameter"]["Value"], "s3cr3t");
+        assert_eq!(body["Parameter"]["Version"], 3);
+    }
+
+    #[test]
+    fn parameter_get_keeps_the_version_selector() {
+        let server = server(&source_backend(), None);
+        let (_, body) = get(
+            &server,
+            "/systemsmanager/parameters/get?name=/src/db/pass&version=3",
+        );
+        assert_eq!(body["Parameter"]["Selector"], ":3");
+    }
+
+    #[test]
+    fn parameter_get_of_a_missing_name_is_404() {
+        let server = server(&source_backend(), None);
+        assert_eq!(
+            get(&server, "/systemsmanager/parameters/get?name=/missing").0,
+            404
+        );
+    }
+
+    #[test]
+    fn parameter_get_without_a_name_is_400() {
+        let server = server(&source_backend(), None);
+        assert_eq!(get(&server, "/systemsmanager/parameters/get").0, 400);
+    }
+
+    #[test]
+    fn get_by_path_lists_recursively() {
+        let server = server(&source_backend(), None);
+        let (status, body) = get(
+            &server,
+            "/systemsmanager/parameters/get-by-path?path=/src&recursive=true",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["Parameters"][0]["Name"], "/src/db/pass");
+    }
+
+    #[test]
+    fn token_is_required_once_set() {
+        let server = server(&source_backend(), Some("t0ken"));
+        let target = "/systemsmanager/parameters/get?name=/src/db/pass";
+        assert_eq!(server.handle("GET", target, &HashMap::new()).status, 401);
+        let auth: HashMap<String, String> =
+            vec![(EXTENSION_TOKEN_HEADER.to_string(), "t0ken".to_string())]
+                .into_iter()
+                .collect();
+        assert_eq!(server.handle("GET", target, &auth).status, 200);
+    }
+
+    #[test]
+    fn responses_are_served_from_the_cache_until_the_ttl() {
+        let backend = source_backend();
+        let server = server(&backend, None);
+        let target = "/systemsmanager/parameters/get?name=/src/db/pass&withDecryption=true";
+        get(&server, target);
+        backend.insert(StoredParameter {
+            name: "/src/db/pass".to_string(),
+            p_type: "SecureString".to_string(),
+            value: "r0tated".to_string(),
+            version: 4,
+            ..Default::default()
+        });
+        assert_eq!(get(&server, target).1["Parameter"]["Value"], "s3cr3t");
+    }
+
+    #[test]
+    fn concurrent_misses_share_one_fetch() {
+        let cache = Arc::new(Cache::new(Duration::from_secs(0)));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (cache, fetches) = (cache.clone(), fetches.clone());
+                std::thread::spawn(move || {
+                    cache.get_or_fetch("key", || {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(200));
+                        Response {
+                            status: 200,
+                            body: "{}".to_string(),
+                        }
+                    })
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .for_each(|t| assert_eq!(t.join().unwrap().status, 200));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("a%2Fb+c%zz"), "a/b c%zz");
+    }
+
+    #[test]
+    fn secret_get_selects_by_stage_or_version_id() {
+        let server = server(&secret_backend(), None);
+        let secret = |target: &str| get(&server, target).1["SecretString"].clone();
+        assert_eq!(secret("/secretsmanager/get?secretId=api"), "n3w");
+        // Cached apart from the current version
+        assert_eq!(
+            secret("/secretsmanager/get?secretId=api&versionStage=AWSPREVIOUS"),
+            "0ld"
+        );
+        assert_eq!(
+            secret("/secretsmanager/get?secretId=api&versionId=1"),
+            "0ld"
+        );
+    }
+
+    #[test]
+    fn secret_get_refuses_both_version_id_and_stage() {
+        let server = server(&secret_backend(), None);
+        let target = "/secretsmanager/get?secretId=api&versionId=1&versionStage=AWSPREVIOUS";
+        assert_eq!(get(&server, target).0, 400);
+    }
+
+    #[test]
+    fn secret_response_has_version_id_and_stages() {
+        let backend = secret_backend();
+        let server = Server::new(
+            &SSMOps::with_client("us-east-1", backend.client()),
+            Duration::from_secs(300),
+            None,
+        );
+        let (status, body) = get(&server, "/secretsmanager/get?secretId=api");
+        assert_eq!(status, 200);
+        assert_eq!(body["VersionId"], "2");
+        assert_eq!(body["VersionStages"], serde_json::json!(["AWSCURRENT"]));
+
+        let (_, body) = get(
+            &server,
+            "/secretsmanager/get?secretId=api&versionStage=AWSPREVIOUS",
+        );
+        assert_eq!(body["SecretString"], "0ld");
+        assert_eq!(body["VersionId"], "1");
+        assert_eq!(body["VersionStages"], serde_json::json!(["AWSPREVIOUS"]));
+    }
+
+    #[test]
+    fn server_reads_through_the_disk_cache() {
+        let backend = secret_backend();
+        let dir =
+            std::env::temp_dir().join(format!("ssm_helper_serve_cache_{}", std::process::id()));
+        let key = dir.with_extension("key");
+        let _ = fs::remove_dir_all(&dir);
+        let server = || {
+            let cache =
+                DiskCache::new(&dir, &key, CacheMode::Prefer, Duration::from_secs(60), None)
+                    .unwrap()
+                    .with_account("123456789012");
+            let ssm = SSMOps::with_client("us-east-1", backend.client()).disk_cache(Some(cache));
+            Server::new(&ssm, Duration::from_secs(0), None)
+        };
+        assert_eq!(
+            get(&server(), "/secretsmanager/get?secretId=api").1["SecretString"],
+            "n3w"
+        );
+
+        backend.insert(StoredParameter {
+            name: "/aws/reference/secretsmanager/api".to_string(),
+            p_type: "SecureString".to_string(),
+            value: "n3wer".to_string(),
+            version: 3,
+            ..Default::default()
+        });
+        // A fresh server, its TTL cache empty: the disk cache still answers
+        assert_eq!(
+            get(&server(), "/secretsmanager/get?secretId=api").1["SecretString"],
+            "n3w"
+        );
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_file(&key);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bind_unix_refuses_a_regular_file() {
+        let path =
+            std::env::temp_dir().join(format!("ssm_helper_not_a_socket_{}", std::process::id()));
+        fs::write(&path, "keep me").unwrap();
+        assert!(bind_unix(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bind_unix_replaces_a_stale_socket_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("ssm_helper_socket_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        drop(bind_unix(&path).unwrap());
+        // The first listener is gone, its socket file is stale
+        let listener = bind_unix(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+}
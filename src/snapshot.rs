@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use age::secrecy::Secret;
+use age::x25519;
+use failure::Error;
+
+use crate::ssm_parameters::relative_name;
+
+/// Environment variable holding the snapshot passphrase, never taken from the command line
+pub const PASSPHRASE_ENV: &str = "SSM_HELPER_PASSPHRASE";
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// `backup` output, written age encrypted
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub format_version: u32,
+    pub path: String,
+    pub region: String,
+    pub created_at: String,
+    pub parameters: Vec<SnapshotParameter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SnapshotParameter {
+    pub name: String,
+    pub p_type: String,
+    /// Decrypted value
+    pub value: String,
+    pub version: Option<i64>,
+    pub description: Option<String>,
+    pub tier: Option<String>,
+    pub allowed_pattern: Option<String>,
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Passphrase (scrypt) or X25519 keys: recipients to encrypt, identities to decrypt
+pub enum SnapshotKey {
+    Passphrase(String),
+    Recipients(Vec<x25519::Recipient>),
+    Identities(Vec<x25519::Identity>),
+}
+
+impl SnapshotKey {
+    /// Identities from an age identity file (AGE-SECRET-KEY-1... lines, # comments)
+    pub fn from_identity_file(contents: &str) -> Result<Self, Error> {
+        let identities = contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| {
+                l.parse::<x25519::Identity>()
+                    .map_err(|e| format_err!("Invalid age identity: {}", e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if identities.is_empty() {
+            bail!("No age identity found");
+        }
+        Ok(SnapshotKey::Identities(identities))
+    }
+}
+
+pub fn passphrase_from_env() -> Result<String, Error> {
+    match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) if !passphrase.is_empty() => Ok(passphrase),
+        _ => bail!(
+            "Set {} (or use age recipients / identities)",
+            PASSPHRASE_ENV
+        ),
+    }
+}
+
+pub fn encrypt_snapshot(plaintext: &[u8], key: &SnapshotKey) -> Result<Vec<u8>, Error> {
+    let encryptor = match key {
+        SnapshotKey::Passphrase(passphrase) => {
+            age::Encryptor::with_user_passphrase(Secret::new(passphrase.clone()))
+        }
+        SnapshotKey::Recipients(recipients) => age::Encryptor::with_recipients(
+            recipients
+                .iter()
+                .map(|r| Box::new(r.clone()) as Box<dyn age::Recipient + Send>)
+                .collect(),
+        )
+        .ok_or_else(|| format_err!("At least one recipient is required"))?,
+        SnapshotKey::Identities(_) => bail!("Snapshots are encrypted to recipients"),
+    };
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+
+    Ok(encrypted)
+}
+
+pub fn decrypt_snapshot(encrypted: &[u8], key: &SnapshotKey) -> Result<Vec<u8>, Error> {
+    let mut reader = match (age::Decryptor::new(encrypted)?, key) {
+        (age::Decryptor::Passphrase(d), SnapshotKey::Passphrase(passphrase)) => {
+            d.decrypt(&Secret::new(passphrase.clone()), None)?
+        }
+        (age::Decryptor::Recipients(d), SnapshotKey::Identities(identities)) => {
+            d.decrypt(identities.iter().map(|i| i as &dyn age::Identity))?
+        }
+        (age::Decryptor::Passphrase(_), _) => {
+            bail!(
+                "The snapshot is passphrase encrypted, set {}",
+                PASSPHRASE_ENV
+            )
+        }
+        (age::Decryptor::Recipients(_), _) => {
+            bail!("The snapshot is encrypted to age recipients, pass --identity")
+        }
+    };
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+
+    Ok(plaintext)
+}
+
+/// --prefix-rewrite old=new, names outside `old` are left alone
+pub fn rewrite_prefix(name: &str, rewrite: &Option<(String, String)>) -> String {
+    match rewrite {
+        Some((old, new)) => {
+            let old = old.trim_end_matches('/');
+            let inside = name == old || name.starts_with(&format!("{}/", old));
+            if inside && !old.is_empty() {
+                match relative_name(old, name) {
+                    "" => new.trim_end_matches('/').to_string(),
+                    rest => format!("{}/{}", new.trim_end_matches('/'), rest),
+                }
+            } else {
+                name.to_string()
+            }
+        }
+        None => name.to_string(),
+    }
+}
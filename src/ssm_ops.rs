@@ -1,18 +1,45 @@
 use serde_json::value::Value as Json;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::{fmt, fs};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-use rusoto_core::{Region, RusotoError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::Future;
+use rusoto_core::request::{BufferedHttpResponse, HttpClient, HttpResponse};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Client, Region, RusotoError};
+use rusoto_credential::{
+    AutoRefreshingProvider, DefaultCredentialsProvider, ProfileProvider, ProvideAwsCredentials,
+};
 use rusoto_ssm::{
+    AddTagsToResourceRequest, DeleteParametersRequest, DescribeParametersRequest,
     GetParameterError, GetParameterRequest, GetParametersByPathRequest, GetParametersRequest,
-    PutParameterError, PutParameterRequest, Ssm, SsmClient,
+    LabelParameterVersionError, LabelParameterVersionRequest, ListTagsForResourceRequest,
+    ParameterStringFilter, PutParameterError, PutParameterRequest, RemoveTagsFromResourceRequest,
+    Ssm, SsmClient, Tag,
+};
+use rusoto_sts::{
+    GetCallerIdentityRequest, GetCallerIdentityResponse, Sts,
+    StsAssumeRoleSessionCredentialsProvider, StsClient,
 };
 
+use crate::audit::{now_timestamp, value_hash, AuditEntry, AuditLog};
+use crate::diff::MASKED_VALUE;
+use crate::disk_cache::{CacheMode, DiskCache};
+use crate::guardrails::{Guard, Guardrails};
+use crate::output::{confirm_typed, write_atomically};
+use crate::plan::{PlannedAction, PlannedOperation, Planner};
+use crate::snapshot::{Snapshot, SnapshotParameter, SNAPSHOT_FORMAT_VERSION};
 use crate::ssm_parameters::{
-    SSMParameter, SSMParameterRequest, SSMParametersByPathRequest, SSMParametersRequest,
-    SSMParametersResult, SSMRequestError,
+    begins_with_path, is_reserved_name, is_secrets_manager_reference, json_key, relative_name,
+    select_tier, with_selector, SSMCloneRequest, SSMDescribeParametersRequest,
+    SSMFindParametersRequest, SSMLabelRequest, SSMParameter, SSMParameterFilter,
+    SSMParameterMetadata, SSMParameterRequest, SSMParametersByPathRequest, SSMParametersRequest,
+    SSMParametersResult, SSMPutParameterRequest, SSMRequestError, SSMSyncRequest,
+    SECRETS_MANAGER_PREFIX, STANDARD_TIER_MAX_VALUE_SIZE,
 };
+use crate::sync::{plan_sync, SyncAction, SyncOperation};
 
 use failure::Error;
 use handlebars::{
@@ -20,10 +47,29 @@ use handlebars::{
     RenderError,
 };
 
+/// Parameters found keyed by requested name (with selector), and the invalid names
+type Fetched = (Vec<(String, SSMParameter)>, Vec<String>);
+
+/// Parameter versions keyed by name (with selector)
+pub type ParameterVersions = BTreeMap<String, i64>;
+
 //#[derive(Debug)]
 pub struct SSMOps {
     region: String,
+    aws_region: Region,
+    /// Signs and dispatches the calls rusoto_ssm doesn't know about (UnlabelParameterVersion)
+    client: Client,
     ssm_client: SsmClient,
+    /// --dry-run: writes are recorded here instead of being sent
+    planner: Option<Planner>,
+    /// Every write sent is appended here
+    audit: Option<AuditLog>,
+    /// Read-only / confirm prefixes, checked before any write is sent (or planned)
+    guard: Guard,
+    /// --cache-mode: GetParameters reads through the encrypted disk cache
+    cache: Option<DiskCache>,
+    /// STS account of the credentials, looked up once
+    account: RefCell<Option<String>>,
 }
 
 impl fmt::Debug for SSMOps {
@@ -34,38 +80,332 @@ impl fmt::Debug for SSMOps {
 
 impl SSMOps {
     pub fn new(region: &str) -> Self {
+        SSMOps::with_client(region, Client::shared())
+    }
+
+    /// Already configured client, ex: the in-memory test dispatcher
+    pub fn with_client(region: &str, client: Client) -> Self {
+        let aws_region = region.parse::<Region>().unwrap_or_default();
         SSMOps {
             region: region.to_string(),
-            ssm_client: SsmClient::new(region.parse::<Region>().unwrap_or_default()),
+            ssm_client: SsmClient::new_with_client(client.clone(), aws_region.clone()),
+            aws_region,
+            client,
+            planner: None,
+            audit: None,
+            guard: Guard::default(),
+            cache: None,
+            account: RefCell::new(None),
+        }
+    }
+
+    /// Custom endpoint (ex: LocalStack), also used by the raw requests. STS keeps its default one.
+    pub fn with_endpoint(mut self, endpoint: Option<&str>) -> Self {
+        if let Some(endpoint) = endpoint {
+            self.aws_region = Region::Custom {
+                name: self.region.clone(),
+                endpoint: endpoint.to_string(),
+            };
+            self.ssm_client =
+                SsmClient::new_with_client(self.client.clone(), self.aws_region.clone());
+        }
+        self
+    }
+
+    /// Client on the same credentials, endpoint, guardrails and disk cache, without the planner
+    /// and audit log, ex: one per `serve` connection
+    pub fn fork(&self) -> SSMOps {
+        SSMOps {
+            region: self.region.clone(),
+            aws_region: self.aws_region.clone(),
+            client: self.client.clone(),
+            ssm_client: SsmClient::new_with_client(self.client.clone(), self.aws_region.clone()),
+            planner: None,
+            audit: None,
+            guard: self.guard.clone(),
+            cache: self.cache.clone(),
+            account: RefCell::new(self.account.borrow().clone()),
+        }
+    }
+
+    /// `fork` whose disk cache (if any) always asks the API, unless offline,
+    /// ex: `template --watch`
+    pub fn refreshing(&self) -> SSMOps {
+        let mut ssm = self.fork();
+        ssm.cache = ssm.cache.map(|cache| match cache.mode() {
+            CacheMode::Offline => cache,
+            _ => cache.with_mode(CacheMode::Refresh),
+        });
+        ssm
+    }
+
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    pub fn guardrails(mut self, rules: Guardrails) -> Self {
+        self.guard = Guard::new(rules);
+        self
+    }
+
+    /// GuardrailError if a rule blocks writing `name`, confirm prefixes prompt unless --dry-run
+    fn guard(&self, name: &str) -> Result<(), Error> {
+        let ask = |prefix: &str| {
+            confirm_typed(
+                &format!("'{}' is under the protected prefix '{}'.", name, prefix),
+                prefix,
+            )
+            .unwrap_or(false)
+        };
+        let ask = if self.planner.is_some() {
+            None
+        } else {
+            Some(ask)
+        };
+        self.guard.allow(name, ask).map_err(Error::from)
+    }
+
+    pub fn audit_log(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn disk_cache(mut self, cache: Option<DiskCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    fn get_caller_identity(&self) -> Result<GetCallerIdentityResponse, Error> {
+        let region = self.region.parse::<Region>().unwrap_or_default();
+        let sts = StsClient::new_with_client(self.client.clone(), region);
+        sts.get_caller_identity(GetCallerIdentityRequest {})
+            .sync()
+            .map_err(|e| failure::err_msg(e.to_string()))
+    }
+
+    /// STS GetCallerIdentity ARN of the credentials in use
+    fn caller_identity(&self) -> String {
+        match self.get_caller_identity() {
+            Ok(output) => output.arn.unwrap_or_else(|| "unknown".to_string()),
+            Err(err) => {
+                warn!("Audit: caller identity unavailable: {}", err);
+                "unknown".to_string()
+            }
         }
     }
 
+    /// STS GetCallerIdentity account of the credentials in use
+    fn caller_account(&self) -> Result<String, Error> {
+        if let Some(account) = self.account.borrow().as_ref() {
+            return Ok(account.clone());
+        }
+        let account = self
+            .get_caller_identity()?
+            .account
+            .ok_or_else(|| failure::err_msg("GetCallerIdentity returned no account"))?;
+        self.account.replace(Some(account.clone()));
+        Ok(account)
+    }
+
+    /// Same region and account: KMS key ids and aliases mean the same key on both
+    fn same_key_scope(&self, target: &SSMOps) -> bool {
+        if self.region != target.region {
+            return false;
+        }
+        match (self.caller_account(), target.caller_account()) {
+            (Ok(a), Ok(b)) => a == b,
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Account unavailable, assuming another one: {}", e);
+                false
+            }
+        }
+    }
+
+    fn audit(
+        &self,
+        action: &str,
+        name: &str,
+        versions: (Option<i64>, Option<i64>),
+        value: Option<&str>,
+        detail: Vec<String>,
+    ) {
+        if let Some(log) = &self.audit {
+            let entry = AuditEntry {
+                timestamp: now_timestamp(),
+                identity: log.identity(|| self.caller_identity()),
+                region: self.region.clone(),
+                action: action.to_string(),
+                name: name.to_string(),
+                old_version: versions.0,
+                new_version: versions.1,
+                value_hash: value.map(value_hash),
+                detail,
+            };
+            // The write already happened, a failing log must not hide that
+            if let Err(e) = log.append(&entry) {
+                error!("Audit log {:?}: {}", log.path(), e);
+            }
+        }
+    }
+
+    /// Current versions of `names` (missing ones left out), only read when auditing
+    fn audited_versions(&self, names: &[String]) -> Result<HashMap<String, i64>, Error> {
+        if self.audit.is_none() {
+            return Ok(HashMap::new());
+        }
+        let output = self
+            .ssm_client
+            .get_parameters(GetParametersRequest {
+                names: names.to_vec(),
+                with_decryption: Some(false),
+            })
+            .sync()
+            .map_err(|err| failure::err_msg(err.to_string()))?;
+
+        Ok(output
+            .parameters
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|p| Some((p.name?, p.version?)))
+            .collect())
+    }
+
+    /// With `enabled`, puts, deletes, tag and label changes are only planned, reads still happen
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.planner = if enabled {
+            Some(Planner::default())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Operations recorded so far in --dry-run mode
+    pub fn planned(&self) -> Vec<PlannedOperation> {
+        self.planner
+            .as_ref()
+            .map(Planner::operations)
+            .unwrap_or_default()
+    }
+
+    fn plan(
+        &self,
+        action: PlannedAction,
+        name: &str,
+        from_version: Option<i64>,
+        detail: Vec<String>,
+    ) -> bool {
+        match &self.planner {
+            Some(planner) => {
+                planner.record(PlannedOperation {
+                    action,
+                    name: name.to_string(),
+                    p_type: None,
+                    value: None,
+                    from_version,
+                    to_version: from_version,
+                    detail: Some(detail.join(", ")),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Current version of `name`, None when it doesn't exist
+    fn current_version(&self, name: &str) -> Result<Option<i64>, Error> {
+        match self
+            .ssm_client
+            .get_parameter(GetParameterRequest {
+                name: name.to_string(),
+                with_decryption: Some(false),
+            })
+            .sync()
+        {
+            Ok(res) => Ok(res.parameter.and_then(|p| p.version)),
+            Err(RusotoError::Service(GetParameterError::ParameterNotFound(_))) => Ok(None),
+            Err(err) => Err(failure::err_msg(err.to_string())),
+        }
+    }
+
+    /// Client for a named profile from the shared credentials file, default chain if None
+    pub fn with_profile(region: &str, profile: Option<&str>) -> Result<Self, Error> {
+        SSMOps::with_credentials(region, profile, None)
+    }
+
+    /// Client for a named profile and/or an assumed role (STS AssumeRole on top of the
+    /// profile or the default chain), ex: another account as clone destination
+    pub fn with_credentials(
+        region: &str,
+        profile: Option<&str>,
+        role_arn: Option<&str>,
+    ) -> Result<Self, Error> {
+        let aws_region = region.parse::<Region>().unwrap_or_default();
+        let client = match (profile, role_arn) {
+            (None, None) => return Ok(SSMOps::new(region)),
+            (Some(profile), None) => {
+                Client::new_with(profile_provider(profile)?, HttpClient::new()?)
+            }
+            (Some(profile), Some(role_arn)) => {
+                assume_role_client(profile_provider(profile)?, role_arn, aws_region)?
+            }
+            (None, Some(role_arn)) => {
+                assume_role_client(DefaultCredentialsProvider::new()?, role_arn, aws_region)?
+            }
+        };
+
+        Ok(SSMOps::with_client(region, client))
+    }
+
     pub fn get_parameters_by_path(
         &self,
         req: &SSMParametersByPathRequest,
     ) -> Result<SSMParametersResult, SSMRequestError> {
+        let mut out_parms: Vec<SSMParameter> = Vec::new();
+        self.get_parameters_by_path_paged(req, |page| out_parms.extend(page))?;
+
+        Ok(SSMParametersResult {
+            parameters: out_parms,
+            invalid_parameters: vec![],
+        })
+    }
+
+    /// GetParametersByPath handing every page to `on_page` as soon as it arrives,
+    /// so callers can stream the output instead of waiting for the whole hierarchy.
+    pub fn get_parameters_by_path_paged<F>(
+        &self,
+        req: &SSMParametersByPathRequest,
+        mut on_page: F,
+    ) -> Result<(), SSMRequestError>
+    where
+        F: FnMut(Vec<SSMParameter>),
+    {
+        if is_secrets_manager_reference(&format!("{}/", req.path.trim_end_matches('/'))) {
+            return Err(SSMRequestError {
+                reason: format!(
+                    "Secrets Manager references can't be listed, get them by name: {}<secret>",
+                    SECRETS_MANAGER_PREFIX
+                ),
+            });
+        }
         let mut input: GetParametersByPathRequest = GetParametersByPathRequest {
             path: req.path.clone(),
             recursive: req.recursive,
             with_decryption: req.with_decryption,
+            parameter_filters: to_parameter_filters(&req.parameter_filters),
             ..Default::default()
         };
-        let mut out_parms: Vec<SSMParameter> = Vec::new();
-        let mut error: Option<SSMRequestError> = None;
+        let mut found = false;
         loop {
             match self.ssm_client.get_parameters_by_path(input.clone()).sync() {
                 Ok(output) => match output.parameters {
                     Some(parameter_list) => {
-                        out_parms.extend(parameter_list.into_iter().map(|p| SSMParameter {
-                            name: p.name,
-                            p_type: p.type_,
-                            value: p.value,
-                            version: p.version,
-                        }));
+                        found = found || !parameter_list.is_empty();
+                        on_page(parameter_list.into_iter().map(SSMParameter::from).collect());
                         match output.next_token {
                             Some(token) => {
                                 input = GetParametersByPathRequest {
-                                    next_token: Some(token.clone()),
+                                    next_token: Some(token),
                                     ..input
                                 };
                             }
@@ -75,65 +415,335 @@ impl SSMOps {
                         }
                     }
                     None => {
-                        println!("No parameters found!");
                         break;
                     }
                 },
                 Err(err) => {
-                    error = Some(SSMRequestError {
+                    return Err(SSMRequestError {
                         reason: err.to_string(),
                     });
-                    break;
                 }
             }
         }
 
-        if let Some(e) = error {
-            return Err(e);
+        if !found {
+            info!("No parameters found under path: {}", req.path);
         }
 
-        Ok(SSMParametersResult {
-            parameters: out_parms,
-            invalid_parameters: vec![],
-        })
+        Ok(())
     }
 
+    /// GetParameters, Secrets Manager references go one by one through GetParameter (decrypted)
     pub fn get_parameters(&self, req: &SSMParametersRequest) -> Result<SSMParametersResult, Error> {
+        let (references, names): (Vec<String>, Vec<String>) = req
+            .names
+            .iter()
+            .cloned()
+            .partition(|n| is_secrets_manager_reference(n));
+        let mut result = SSMParametersResult::default();
+        if !names.is_empty() {
+            let (parameters, invalid) =
+                self.read_through(names, req.with_decryption.unwrap_or(false), |names| {
+                    self.fetch_parameters(names, req.with_decryption)
+                })?;
+            result.parameters = parameters;
+            result.invalid_parameters = invalid;
+        }
+        if !references.is_empty() {
+            // Always decrypted
+            let (parameters, invalid) =
+                self.read_through(references, true, |names| self.fetch_references(names))?;
+            result.parameters.extend(parameters);
+            result.invalid_parameters.extend(invalid);
+        }
+
+        if !result.invalid_parameters.is_empty() {
+            let parm_list: String = result
+                .invalid_parameters
+                .iter()
+                .map(|p| format!("{}, ", p))
+                .collect();
+            let out_msg: String = format!("Invalid Parameters: {}", parm_list);
+            if result.parameters.is_empty() {
+                return Err(failure::err_msg(out_msg));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// `fetch` through the disk cache if there is one: the parameters found and the invalid names
+    fn read_through<F>(
+        &self,
+        names: Vec<String>,
+        decrypt: bool,
+        fetch: F,
+    ) -> Result<(Vec<SSMParameter>, Vec<String>), Error>
+    where
+        F: FnOnce(Vec<String>) -> Result<Fetched, Error>,
+    {
+        match &self.cache {
+            Some(cache) => self.cached_parameters(cache, names, decrypt, fetch),
+            None => {
+                let (fetched, invalid) = fetch(names)?;
+                Ok((fetched.into_iter().map(|(_, p)| p).collect(), invalid))
+            }
+        }
+    }
+
+    /// GetParameters: the parameters found (keyed by requested name) and the invalid names
+    fn fetch_parameters(
+        &self,
+        names: Vec<String>,
+        with_decryption: Option<bool>,
+    ) -> Result<Fetched, Error> {
         let input: GetParametersRequest = GetParametersRequest {
-            names: req.names.clone(),
-            with_decryption: req.with_decryption,
+            names,
+            with_decryption,
         };
         match self.ssm_client.get_parameters(input).sync() {
-            Ok(output) => {
-                let invalid_parameters = output.invalid_parameters.unwrap();
-                let valid_parameters = output.parameters.unwrap();
-                if !invalid_parameters.is_empty() {
-                    let parm_list: String = invalid_parameters
-                        .iter()
-                        .map(|p| format!("{}, ", p))
-                        .collect();
-                    let out_msg: String = format!("Invalid Parameters: {}", parm_list);
-                    if valid_parameters.is_empty() {
-                        return Err(failure::err_msg(out_msg));
+            Ok(output) => Ok((
+                output
+                    .parameters
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|p| {
+                        let p = SSMParameter::from(p);
+                        // The requested name, with its selector (ex: /name:3, /name:stable)
+                        let name = format!(
+                            "{}{}",
+                            p.name.as_deref().unwrap_or_default(),
+                            p.selector.as_deref().unwrap_or_default()
+                        );
+                        (name, p)
+                    })
+                    .collect(),
+                output.invalid_parameters.unwrap_or_default(),
+            )),
+            Err(err) => Err(failure::err_msg(err.to_string())),
+        }
+    }
+
+    /// Secrets Manager references one by one (GetParameters doesn't take them)
+    fn fetch_references(&self, names: Vec<String>) -> Result<Fetched, Error> {
+        let mut found = Vec::new();
+        let mut invalid = Vec::new();
+        for name in names {
+            match self.get_reference(&name)? {
+                Some(parameter) => found.push((name, parameter)),
+                None => invalid.push(name),
+            }
+        }
+        Ok((found, invalid))
+    }
+
+    /// `fetch` through the disk cache: prefer serves fresh entries and falls back to
+    /// expired ones when the API fails, refresh always fetches, offline never does
+    fn cached_parameters<F>(
+        &self,
+        cache: &DiskCache,
+        names: Vec<String>,
+        decrypt: bool,
+        fetch: F,
+    ) -> Result<(Vec<SSMParameter>, Vec<String>), Error>
+    where
+        F: FnOnce(Vec<String>) -> Result<Fetched, Error>,
+    {
+        let account = cache.account(&self.region, || self.caller_account())?;
+        let mut parameters: Vec<SSMParameter> = Vec::new();
+        let mut expired: Vec<(String, SSMParameter)> = Vec::new();
+        let mut missing: Vec<String> = Vec::new();
+        for name in names {
+            let entry = match cache.mode() {
+                CacheMode::Refresh => None,
+                _ => cache.read(&self.region, &account, &name, decrypt)?,
+            };
+            match entry {
+                Some((entry, fresh)) if fresh || cache.mode() == CacheMode::Offline => {
+                    trace!("Cache hit: {}", name);
+                    parameters.push(entry.parameter);
+                }
+                Some((entry, _)) => {
+                    expired.push((name.clone(), entry.parameter));
+                    missing.push(name);
+                }
+                None => missing.push(name),
+            }
+        }
+        if missing.is_empty() || cache.mode() == CacheMode::Offline {
+            return Ok((parameters, missing));
+        }
+
+        match fetch(missing.clone()) {
+            Ok((fetched, invalid)) => {
+                for (name, p) in fetched {
+                    if let Err(e) = cache.write(&self.region, &account, &name, decrypt, &p) {
+                        warn!("Cache: {}: {}", name, e);
                     }
+                    parameters.push(p);
                 }
-                let out_parms: Vec<SSMParameter> = valid_parameters
+                Ok((parameters, invalid))
+            }
+            Err(e) if cache.mode() == CacheMode::Prefer && !expired.is_empty() => {
+                warn!(
+                    "{}, using {} cached parameter(s) past their TTL",
+                    e,
+                    expired.len()
+                );
+                let invalid = missing
                     .into_iter()
-                    .map(|p| SSMParameter {
-                        name: p.name,
-                        p_type: p.type_,
-                        value: p.value,
-                        version: p.version,
-                    })
+                    .filter(|name| !expired.iter().any(|(n, _)| n == name))
                     .collect();
+                parameters.extend(expired.into_iter().map(|(_, p)| p));
+                Ok((parameters, invalid))
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-                Ok(SSMParametersResult {
-                    parameters: out_parms,
-                    invalid_parameters,
-                })
+    /// Secrets Manager secret through its Parameter Store reference, None if it doesn't exist
+    fn get_reference(&self, name: &str) -> Result<Option<SSMParameter>, Error> {
+        self.lookup(name, true)
+            .map_err(|e| format_err!("Secrets Manager reference {}", e))
+    }
+
+    /// `lookup` through the disk cache if there is one (references are always decrypted)
+    pub fn read_one(
+        &self,
+        name: &str,
+        with_decryption: bool,
+    ) -> Result<Option<SSMParameter>, Error> {
+        if self.cache.is_none() {
+            return self.lookup(name, with_decryption);
+        }
+        let names = vec![name.to_string()];
+        let (mut found, _) = if is_secrets_manager_reference(name) {
+            self.read_through(names, true, |names| self.fetch_references(names))?
+        } else {
+            self.read_through(names, with_decryption, |names| {
+                self.fetch_parameters(names, Some(with_decryption))
+            })?
+        };
+        Ok(found.pop())
+    }
+
+    /// GetParameter (NAME:version / NAME:label selectors accepted), None if it doesn't exist
+    pub fn lookup(&self, name: &str, with_decryption: bool) -> Result<Option<SSMParameter>, Error> {
+        let input = GetParameterRequest {
+            name: name.to_string(),
+            with_decryption: Some(with_decryption),
+        };
+        match self.ssm_client.get_parameter(input).sync() {
+            Ok(res) => Ok(res.parameter.map(SSMParameter::from)),
+            Err(RusotoError::Service(GetParameterError::ParameterNotFound(_)))
+            | Err(RusotoError::Service(GetParameterError::ParameterVersionNotFound(_))) => Ok(None),
+            // ex: AccessDeniedException, secretsmanager:GetSecretValue or kms:Decrypt missing
+            Err(RusotoError::Unknown(res)) => Err(format_err!("{}: {}", name, res.body_as_str())),
+            Err(err) => Err(format_err!("{}: {}", name, err)),
+        }
+    }
+
+    pub fn describe_parameters(
+        &self,
+        req: &SSMDescribeParametersRequest,
+    ) -> Result<Vec<SSMParameterMetadata>, Error> {
+        let mut filters = req.filters.clone();
+        if !req.names.is_empty() {
+            filters.push(SSMParameterFilter::new("Name", "Equals", req.names.clone()));
+        }
+        let mut input: DescribeParametersRequest = DescribeParametersRequest {
+            parameter_filters: to_parameter_filters(&filters),
+            ..Default::default()
+        };
+        let mut out_parms: Vec<SSMParameterMetadata> = Vec::new();
+        loop {
+            let output = self
+                .ssm_client
+                .describe_parameters(input.clone())
+                .sync()
+                .map_err(|err| failure::err_msg(err.to_string()))?;
+            out_parms.extend(
+                output
+                    .parameters
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(SSMParameterMetadata::from),
+            );
+            match output.next_token {
+                Some(token) => {
+                    input = DescribeParametersRequest {
+                        next_token: Some(token),
+                        ..input
+                    };
+                }
+                None => break,
             }
-            Err(err) => Err(failure::err_msg(err.to_string())),
         }
+
+        let missing: Vec<&String> = req
+            .names
+            .iter()
+            .filter(|n| !out_parms.iter().any(|p| p.name.as_ref() == Some(n)))
+            .collect();
+        if !missing.is_empty() {
+            warn!("Parameters not found: {:?}", missing);
+            if missing.iter().any(|n| is_reserved_name(n)) {
+                warn!("Public parameters and Secrets Manager references can't be described");
+            }
+        }
+
+        Ok(out_parms)
+    }
+
+    pub fn find_parameters(
+        &self,
+        req: &SSMFindParametersRequest,
+    ) -> Result<Vec<SSMParameterMetadata>, Error> {
+        let mut found = self.describe_parameters(&SSMDescribeParametersRequest {
+            names: vec![],
+            filters: req.filters.clone(),
+        })?;
+
+        // DescribeParameters does not support the Label filter, GetParametersByPath does,
+        // from the --begins-with hierarchy
+        if let Some(label) = &req.label {
+            let path = begins_with_path(&req.filters);
+            if path == "/" {
+                warn!("--label reads every parameter of the account, narrow it with --begins-with /path");
+            }
+            let labeled: Vec<String> = self
+                .get_parameters_by_path(&SSMParametersByPathRequest {
+                    path,
+                    recursive: Some(true),
+                    with_decryption: Some(false),
+                    parameter_filters: vec![SSMParameterFilter::new(
+                        "Label",
+                        "Equals",
+                        vec![label.clone()],
+                    )],
+                })
+                .map_err(|e| format_err!("{}", e))?
+                .parameters
+                .into_iter()
+                .filter_map(|p| p.name)
+                .collect();
+            found.retain(|p| p.name.as_ref().is_some_and(|n| labeled.contains(n)));
+        }
+
+        if let Some(re) = &req.name_regex {
+            found.retain(|p| p.name.as_ref().is_some_and(|n| re.is_match(n)));
+        }
+
+        if let Some(since) = req.modified_since {
+            found.retain(|p| {
+                p.last_modified_date
+                    .as_ref()
+                    .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+                    .is_some_and(|d| d.with_timezone(&Utc) >= since)
+            });
+        }
+
+        Ok(found)
     }
 
     pub fn process_template(
@@ -148,6 +758,25 @@ impl SSMOps {
             ))); // Return early as an Error, must be a valid file
         }
 
+        let (template_rendered, _, _) = self.render_template(&template_in)?;
+        match template_out {
+            Some(out_file) => {
+                write_atomically(&out_file, template_rendered.as_bytes())?;
+            }
+            None => {
+                println!("{}", template_rendered);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `template_in` rendered with its parameters fetched decrypted, their names (with selectors)
+    /// and the versions that were rendered, from the same GetParameters response
+    pub fn render_template(
+        &self,
+        template_in: &Path,
+    ) -> Result<(String, Vec<String>, ParameterVersions), Error> {
         let mut handlebars = Handlebars::new();
         handlebars.set_strict_mode(true);
 
@@ -167,14 +796,31 @@ impl SSMOps {
                     //                    println!("CTX: {:#?}", ctx);
                     //                    println!("PARAM: {:#?}", param);
 
+                    let key = selector_key(
+                        param.value(),
+                        h.hash_get("version").map(|v| v.value()),
+                        h.hash_get("label").map(|v| v.value()),
+                    )
+                    .map_err(RenderError::new)?;
                     let null = Json::Null;
                     let value = match ctx.data().as_object() {
                         None => &null,
-                        Some(ref o) => o.get(param.value().as_str().unwrap()).unwrap(),
+                        Some(o) => o.get(&key).ok_or_else(|| {
+                            RenderError::new(format!("SSM Parameter not retrieved: {}", key))
+                        })?,
                     };
 
                     //                    let rendered = format!("{}->{}", param.value().render(), value.render());
-                    let rendered = format!("{}", value.render());
+                    let rendered = match h.hash_get("key").map(|k| k.value()) {
+                        None => value.render(),
+                        // {{ssm "/aws/reference/secretsmanager/db" key="password"}}
+                        Some(k) => json_key(
+                            &value.render(),
+                            k.as_str()
+                                .ok_or_else(|| RenderError::new("SSM key must be a string."))?,
+                        )
+                        .map_err(|e| RenderError::new(format!("{}: {}", key, e)))?,
+                    };
                     out.write(rendered.as_ref())?;
 
                     Ok(())
@@ -182,91 +828,591 @@ impl SSMOps {
             ),
         );
 
-        if let Err(error) = handlebars.register_template_file("template", &template_in.as_path()) {
+        if let Err(error) = handlebars.register_template_file("template", template_in) {
             //            println!("TEMPLATE ERROR: {:#?}", error);
             bail!(error);
         }
 
-        let parameter_list: Vec<String> =
-            self.extract_parameters(handlebars.get_template("template"))?;
-
-        //        for p in &parameter_list {
-        //            println!("PARAMETER: {:#?}", p);
-        //        }
-
-        let parameter_list = self.retrieve_parameters(parameter_list)?;
+        let names: Vec<String> = self.extract_parameters(handlebars.get_template("template"))?;
+        let (parameter_list, versions) = self.retrieve_parameters(names.clone())?;
 
         match handlebars.render("template", &parameter_list) {
-            Err(e) => {
-                bail!(e);
-            }
-            Ok(template_rendered) => match template_out {
-                Some(out_file) => {
-                    fs::write(out_file, template_rendered)?;
-                }
-                None => {
-                    println!("{}", template_rendered);
-                }
-            },
+            Err(e) => bail!(e),
+            Ok(template_rendered) => Ok((template_rendered, names, versions)),
         }
+    }
 
-        Ok(())
+    /// Versions of `names` (with selectors, as extracted from a template), no decryption,
+    /// missing ones left out
+    pub fn parameter_versions(&self, names: &[String]) -> Result<ParameterVersions, Error> {
+        let result = self.get_parameters(&SSMParametersRequest {
+            with_decryption: Some(false),
+            names: names.to_vec(),
+        })?;
+
+        Ok(result
+            .parameters
+            .into_iter()
+            .filter_map(|p| {
+                let key = format!("{}{}", p.name?, p.selector.unwrap_or_default());
+                Some((key, p.version?))
+            })
+            .collect())
     }
 
-    pub fn clone_parameter(
-        &self,
-        origin: String,
-        destination: String,
-        overwrite: bool,
-    ) -> Result<(), Error> {
-        println!("Origin: {:#?} - Destination: {:#?}", origin, destination);
+    /// Copy a parameter, `target` may be a client for another region / account.
+    /// The value is read decrypted and written back with the source (or overridden) KMS key,
+    /// description, tier (unless overridden), allowed pattern, policies and tags (unless
+    /// `copy_tags` is off) are carried over, `req.tags` are added on top, replacing source tags
+    /// with the same key.
+    pub fn clone_parameter(&self, target: &SSMOps, req: &SSMCloneRequest) -> Result<(), Error> {
+        debug!(
+            "Origin: {} ({}) - Destination: {} ({})",
+            req.origin, self.region, req.destination, target.region
+        );
 
         let source: SSMParameterRequest = SSMParameterRequest {
-            name: origin.clone(),
-            with_decryption: Some(false),
+            name: req.origin.clone(),
+            with_decryption: Some(true),
         };
 
         let source_param = self.get_one(source)?;
+        let metadata = self
+            .describe_parameters(&SSMDescribeParametersRequest {
+                names: vec![req.origin.clone()],
+                filters: vec![],
+            })?
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("The parameter '{}' could not be described.", req.origin))?;
+        let mut tags = if req.copy_tags {
+            self.list_tags(&req.origin)?
+        } else {
+            vec![]
+        };
+        tags.retain(|(key, _)| !req.tags.iter().any(|(k, _)| k == key));
+        tags.extend(req.tags.iter().cloned());
 
+        // The source key only exists in the source region and account, the target's
+        // default aws/ssm key is used there unless --dest-kms-key-id
+        let key_id = match (source_param.p_type.as_deref(), &req.key_id) {
+            (Some("SecureString"), Some(key_id)) => Some(key_id.clone()),
+            (Some("SecureString"), None) if self.same_key_scope(target) => metadata.key_id.clone(),
+            (Some("SecureString"), None) => {
+                warn!(
+                    "{}: KMS key {} belongs to the source region / account, using the default aws/ssm key of {}",
+                    req.destination,
+                    metadata.key_id.as_deref().unwrap_or_default(),
+                    target.region
+                );
+                None
+            }
+            _ => None,
+        };
         let dest: SSMParameter = SSMParameter {
-            name: Some(destination.clone()),
+            name: Some(req.destination.clone()),
             p_type: source_param.p_type,
             value: source_param.value,
-            version: None,
+            ..Default::default()
         };
 
-        self.put_one(dest, overwrite)?;
+        target.put_one(SSMPutParameterRequest {
+            parameter: dest,
+            overwrite: req.overwrite,
+            key_id,
+            policies: metadata.policies_json(),
+            description: metadata.description,
+            tier: req.tier.clone().or(metadata.tier),
+            allowed_pattern: metadata.allowed_pattern,
+            tags,
+        })?;
+
+        Ok(())
+    }
+
+    /// Clone every parameter under the origin prefix, renamed under the destination prefix
+    pub fn clone_recursive(&self, target: &SSMOps, req: &SSMCloneRequest) -> Result<(), Error> {
+        debug!(
+            "Origin: {} - Destination: {} - Overwrite: {}",
+            req.origin, req.destination, req.overwrite
+        );
+
+        let names: Vec<String> = self
+            .get_parameters_by_path(&SSMParametersByPathRequest {
+                path: req.origin.clone(),
+                recursive: Some(true),
+                with_decryption: Some(false),
+                parameter_filters: vec![],
+            })
+            .map_err(|e| format_err!("{}", e))?
+            .parameters
+            .into_iter()
+            .filter_map(|p| p.name)
+            .collect();
+
+        for name in names {
+            let destination = format!(
+                "{}/{}",
+                req.destination.trim_end_matches('/'),
+                relative_name(&req.origin, &name)
+            );
+            info!("Cloning {} => {}", name, destination);
+            self.clone_parameter(
+                target,
+                &SSMCloneRequest {
+                    origin: name,
+                    destination,
+                    ..req.clone()
+                },
+            )?;
+        }
 
         Ok(())
     }
 
-    pub fn clone_recursive(
+    /// Make destination_path (possibly on another client) mirror source_path
+    pub fn sync_parameters(
         &self,
-        origin: String,
-        destination: String,
-        overwrite: bool,
-    ) -> Result<(), Error> {
-        println!("Origin: {:#?} - Destination: {:#?} - Overwrite: {:#?}", origin, destination, overwrite);
-
-//        let source: SSMParameterRequest = SSMParameterRequest {
-//            name: origin.clone(),
-//            with_decryption: Some(false),
-//        };
-//
-//        let source_param = self.get_one(source)?;
-//
-//        let dest: SSMParameter = SSMParameter {
-//            name: Some(destination.clone()),
-//            p_type: source_param.p_type,
-//            value: source_param.value,
-//            version: None,
-//        };
-//
-//        self.put_one(dest, overwrite)?;
+        destination: &SSMOps,
+        req: &SSMSyncRequest,
+    ) -> Result<Vec<SyncOperation>, Error> {
+        debug!(
+            "Sync Origin: {} ({}) - Destination: {} ({})",
+            req.source_path, self.region, req.destination_path, destination.region
+        );
+        let by_path = |ssm: &SSMOps, path: &str| {
+            ssm.get_parameters_by_path(&SSMParametersByPathRequest {
+                path: path.to_string(),
+                recursive: Some(true),
+                with_decryption: Some(true),
+                parameter_filters: vec![],
+            })
+            .map(|r| r.parameters)
+            .map_err(|e| format_err!("{}", e))
+        };
+
+        let operations = plan_sync(
+            &req.source_path,
+            by_path(self, &req.source_path)?,
+            &req.destination_path,
+            by_path(destination, &req.destination_path)?,
+            &req.filter,
+        );
+
+        for op in operations
+            .iter()
+            .filter(|op| op.action != SyncAction::Delete)
+        {
+            destination.put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: Some(op.name.clone()),
+                    p_type: op.p_type.clone(),
+                    value: op.value.clone(),
+                    ..Default::default()
+                },
+                overwrite: op.action == SyncAction::Overwrite,
+                tier: req.tier.clone(),
+                ..Default::default()
+            })?;
+        }
+        let deletes: Vec<String> = operations
+            .iter()
+            .filter(|op| op.action == SyncAction::Delete)
+            .map(|op| op.name.clone())
+            .collect();
+        destination.delete_parameters(&deletes)?;
+
+        Ok(operations)
+    }
+
+    /// Everything under `path` a restore needs: decrypted values, descriptions, tiers and tags
+    pub fn backup(&self, path: &str) -> Result<Snapshot, Error> {
+        let parameters = self
+            .get_parameters_by_path(&SSMParametersByPathRequest {
+                path: path.to_string(),
+                recursive: Some(true),
+                with_decryption: Some(true),
+                parameter_filters: vec![],
+            })
+            .map_err(|e| format_err!("{}", e))?
+            .parameters;
+        let mut metadata: HashMap<String, SSMParameterMetadata> = self
+            .describe_parameters(&SSMDescribeParametersRequest {
+                names: vec![],
+                filters: vec![SSMParameterFilter::new(
+                    "Path",
+                    "Recursive",
+                    vec![path.to_string()],
+                )],
+            })?
+            .into_iter()
+            .filter_map(|m| Some((m.name.clone()?, m)))
+            .collect();
+
+        let mut out_parms: Vec<SnapshotParameter> = Vec::new();
+        for p in parameters {
+            let name = p.name.unwrap_or_default();
+            let meta = metadata.remove(&name).unwrap_or_default();
+            debug!("Backup: {} - Version: {:?}", name, p.version);
+            out_parms.push(SnapshotParameter {
+                tags: self.list_tags(&name)?.into_iter().collect(),
+                name,
+                p_type: p.p_type.unwrap_or_default(),
+                value: p.value.unwrap_or_default(),
+                version: p.version,
+                description: meta.description,
+                tier: meta.tier,
+                allowed_pattern: meta.allowed_pattern,
+            });
+        }
+
+        Ok(Snapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            path: path.to_string(),
+            region: self.region.clone(),
+            created_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            parameters: out_parms,
+        })
+    }
+
+    /// What restoring `parameters` would do: create missing ones, overwrite different ones
+    pub fn plan_restore(
+        &self,
+        parameters: &[SnapshotParameter],
+    ) -> Result<Vec<SyncOperation>, Error> {
+        let names: Vec<String> = parameters.iter().map(|p| p.name.clone()).collect();
+        let mut current: HashMap<String, SSMParameter> = HashMap::new();
+        for batch in names.chunks(10) {
+            let output = self
+                .ssm_client
+                .get_parameters(GetParametersRequest {
+                    names: batch.to_vec(),
+                    with_decryption: Some(true),
+                })
+                .sync()
+                .map_err(|err| failure::err_msg(err.to_string()))?;
+            for p in output.parameters.unwrap_or_default() {
+                current.insert(p.name.clone().unwrap_or_default(), SSMParameter::from(p));
+            }
+        }
+
+        Ok(parameters
+            .iter()
+            .filter_map(|p| {
+                let action = match current.get(&p.name) {
+                    None => SyncAction::Create,
+                    Some(c)
+                        if c.p_type.as_deref() != Some(&p.p_type)
+                            || c.value.as_deref() != Some(&p.value) =>
+                    {
+                        SyncAction::Overwrite
+                    }
+                    Some(_) => return None,
+                };
+                Some(SyncOperation {
+                    action,
+                    name: p.name.clone(),
+                    p_type: Some(p.p_type.clone()),
+                    value: Some(p.value.clone()),
+                })
+            })
+            .collect())
+    }
+
+    /// Apply planned restore operations through put_one, with description, tier, pattern and tags
+    pub fn restore(
+        &self,
+        parameters: &[SnapshotParameter],
+        operations: &[SyncOperation],
+    ) -> Result<usize, Error> {
+        let mut restored = 0;
+        for op in operations {
+            let p = match parameters.iter().find(|p| p.name == op.name) {
+                Some(p) => p,
+                None => continue,
+            };
+            info!("Restoring {}", p.name);
+            self.put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: Some(p.name.clone()),
+                    p_type: Some(p.p_type.clone()),
+                    value: Some(p.value.clone()),
+                    ..Default::default()
+                },
+                overwrite: op.action == SyncAction::Overwrite,
+                description: p.description.clone(),
+                tier: p.tier.clone(),
+                allowed_pattern: p.allowed_pattern.clone(),
+                tags: p.tags.clone().into_iter().collect(),
+                ..Default::default()
+            })?;
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+
+    /// DeleteParameters, in batches of 10 names (API limit)
+    pub fn delete_parameters(&self, names: &[String]) -> Result<Vec<String>, Error> {
+        for name in names {
+            self.guard(name)?;
+        }
+        let mut deleted: Vec<String> = Vec::new();
+        if let Some(planner) = &self.planner {
+            for batch in names.chunks(10) {
+                let output = self
+                    .ssm_client
+                    .get_parameters(GetParametersRequest {
+                        names: batch.to_vec(),
+                        with_decryption: Some(false),
+                    })
+                    .sync()
+                    .map_err(|err| failure::err_msg(err.to_string()))?;
+                let invalid = output.invalid_parameters.unwrap_or_default();
+                if !invalid.is_empty() {
+                    warn!("Parameters not deleted (invalid): {}", invalid.join(", "));
+                }
+                for p in output.parameters.unwrap_or_default() {
+                    planner.record(PlannedOperation {
+                        action: PlannedAction::Delete,
+                        name: p.name.clone().unwrap_or_default(),
+                        p_type: p.type_,
+                        value: None,
+                        from_version: p.version,
+                        to_version: None,
+                        detail: None,
+                    });
+                    deleted.extend(p.name);
+                }
+            }
+            return Ok(deleted);
+        }
+        for batch in names.chunks(10) {
+            let versions = self.audited_versions(batch)?;
+            let output = self
+                .ssm_client
+                .delete_parameters(DeleteParametersRequest {
+                    names: batch.to_vec(),
+                })
+                .sync()
+                .map_err(|err| failure::err_msg(err.to_string()))?;
+            let invalid = output.invalid_parameters.unwrap_or_default();
+            if !invalid.is_empty() {
+                warn!("Parameters not deleted (invalid): {}", invalid.join(", "));
+            }
+            let batch_deleted = output.deleted_parameters.unwrap_or_default();
+            debug!("DeleteParameters: {}", batch_deleted.join(", "));
+            for name in &batch_deleted {
+                let old_version = versions.get(name).cloned();
+                self.audit("delete", name, (old_version, None), None, vec![]);
+            }
+            deleted.extend(batch_deleted);
+        }
+
+        Ok(deleted)
+    }
+
+    /// ListTagsForResource, as Key=Value pairs
+    pub fn list_tags(&self, name: &str) -> Result<Vec<(String, String)>, Error> {
+        let output = self
+            .ssm_client
+            .list_tags_for_resource(ListTagsForResourceRequest {
+                resource_id: name.to_string(),
+                resource_type: "Parameter".to_string(),
+            })
+            .sync()
+            .map_err(|err| failure::err_msg(err.to_string()))?;
+
+        Ok(output
+            .tag_list
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| (t.key, t.value))
+            .collect())
+    }
+
+    /// AddTagsToResource, existing keys are overwritten
+    pub fn add_tags(&self, name: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        self.guard(name)?;
+        let keys = tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        if self.plan(PlannedAction::Tag, name, None, keys) {
+            return Ok(());
+        }
+        self.ssm_client
+            .add_tags_to_resource(AddTagsToResourceRequest {
+                resource_id: name.to_string(),
+                resource_type: "Parameter".to_string(),
+                tags: to_tags(tags),
+            })
+            .sync()
+            .map_err(|err| failure::err_msg(err.to_string()))?;
+        debug!("AddTagsToResource: {} - {} tag(s)", name, tags.len());
+        let keys = tags.iter().map(|(k, _)| k.clone()).collect();
+        self.audit("tag", name, (None, None), None, keys);
 
         Ok(())
     }
 
+    /// RemoveTagsFromResource, unknown keys are ignored
+    pub fn remove_tags(&self, name: &str, keys: &[String]) -> Result<(), Error> {
+        self.guard(name)?;
+        if self.plan(PlannedAction::Untag, name, None, keys.to_vec()) {
+            return Ok(());
+        }
+        self.ssm_client
+            .remove_tags_from_resource(RemoveTagsFromResourceRequest {
+                resource_id: name.to_string(),
+                resource_type: "Parameter".to_string(),
+                tag_keys: keys.to_vec(),
+            })
+            .sync()
+            .map_err(|err| failure::err_msg(err.to_string()))?;
+        debug!("RemoveTagsFromResource: {} - {} key(s)", name, keys.len());
+        self.audit("untag", name, (None, None), None, keys.to_vec());
+
+        Ok(())
+    }
+
+    /// Put every parameter (name, type and value) read back from `get` / `list` JSON lines output
+    pub fn import_parameters(
+        &self,
+        parameters: Vec<SSMParameter>,
+        overwrite: bool,
+        tags: &[(String, String)],
+        tier: Option<&str>,
+    ) -> Result<usize, Error> {
+        let mut imported = 0;
+        for (line, p) in parameters.into_iter().enumerate() {
+            if p.name.is_none() || p.p_type.is_none() || p.value.is_none() {
+                bail!(
+                    "Import: name, p_type and value are required (entry {})",
+                    line + 1
+                );
+            }
+            info!("Importing {}", p.name.as_deref().unwrap_or_default());
+            self.put_one(SSMPutParameterRequest {
+                parameter: SSMParameter {
+                    name: p.name,
+                    p_type: p.p_type,
+                    value: p.value,
+                    ..Default::default()
+                },
+                overwrite,
+                tags: tags.to_vec(),
+                tier: tier.map(String::from),
+                ..Default::default()
+            })?;
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+
+    /// LabelParameterVersion, the latest version if `req.version` is None, returns the labels
+    /// Parameter Store refused. A label already on another version of the parameter is moved.
+    pub fn label_parameter(&self, req: &SSMLabelRequest) -> Result<Vec<String>, Error> {
+        self.guard(&req.name)?;
+        if self.planner.is_some() {
+            let version = match req.version {
+                Some(version) => Some(version),
+                None => self.current_version(&req.name)?,
+            };
+            self.plan(PlannedAction::Label, &req.name, version, req.labels.clone());
+            return Ok(vec![]);
+        }
+        let input = LabelParameterVersionRequest {
+            name: req.name.clone(),
+            labels: req.labels.clone(),
+            parameter_version: req.version,
+        };
+        match self.ssm_client.label_parameter_version(input).sync() {
+            Err(RusotoError::Service(s_err)) => match s_err {
+                LabelParameterVersionError::InternalServerError(_) => {
+                    Err(failure::err_msg("An error occurred on the server side."))
+                }
+                LabelParameterVersionError::ParameterNotFound(_) => Err(format_err!(
+                    "The parameter '{}' could not be found. Verify the name and try again.",
+                    req.name
+                )),
+                LabelParameterVersionError::ParameterVersionLabelLimitExceeded(_) => Err(
+                    failure::err_msg("A parameter version can have a maximum of ten labels."),
+                ),
+                LabelParameterVersionError::ParameterVersionNotFound(_) => Err(format_err!(
+                    "The version {:?} of '{}' was not found.",
+                    req.version,
+                    req.name
+                )),
+                LabelParameterVersionError::TooManyUpdates(_) => Err(failure::err_msg(
+                    "There are concurrent updates for a resource that supports one update at a time.",
+                )),
+            },
+            Err(err) => Err(failure::err_msg(err.to_string())),
+            Ok(output) => {
+                let invalid = output.invalid_labels.unwrap_or_default();
+                debug!("LabelParameterVersion: {} - {:?}", req.name, req.labels);
+                let applied = req
+                    .labels
+                    .iter()
+                    .filter(|l| !invalid.contains(l))
+                    .cloned()
+                    .collect();
+                let version = (req.version, req.version);
+                self.audit("label", &req.name, version, None, applied);
+                Ok(invalid)
+            }
+        }
+    }
+
+    /// UnlabelParameterVersion, sent as a raw request (not in this rusoto_ssm release),
+    /// returns the removed labels
+    pub fn unlabel_parameter(&self, req: &SSMLabelRequest) -> Result<Vec<String>, Error> {
+        self.guard(&req.name)?;
+        let version = req
+            .version
+            .ok_or_else(|| format_err!("Unlabel: a parameter version is required"))?;
+        if self.plan(
+            PlannedAction::Unlabel,
+            &req.name,
+            Some(version),
+            req.labels.clone(),
+        ) {
+            return Ok(req.labels.clone());
+        }
+        let mut request = SignedRequest::new("POST", "ssm", &self.aws_region, "/");
+        request.set_content_type("application/x-amz-json-1.1".to_owned());
+        request.add_header("x-amz-target", "AmazonSSM.UnlabelParameterVersion");
+        request.set_payload(Some(
+            serde_json::json!({
+                "Name": req.name,
+                "ParameterVersion": version,
+                "Labels": req.labels,
+            })
+            .to_string(),
+        ));
+
+        let output = self
+            .client
+            .sign_and_dispatch(request, json_response)
+            .sync()
+            .map_err(|err| match err {
+                RusotoError::Service(message) => failure::err_msg(message),
+                err => failure::err_msg(format!("{:?}", err)),
+            })?;
+        let labels = |key: &str| -> Vec<String> {
+            serde_json::from_value(output[key].clone()).unwrap_or_default()
+        };
+        let invalid = labels("InvalidLabels");
+        if !invalid.is_empty() {
+            warn!("Labels not on version {}: {}", version, invalid.join(", "));
+        }
+        debug!("UnlabelParameterVersion: {} - {:?}", req.name, req.labels);
+        let removed = labels("RemovedLabels");
+        let version = (Some(version), Some(version));
+        self.audit("unlabel", &req.name, version, None, removed.clone());
+
+        Ok(removed)
+    }
+
     fn get_one(&self, parameter: SSMParameterRequest) -> Result<SSMParameter, Error> {
         let input = GetParameterRequest {
             name: parameter.name.clone(),
@@ -290,27 +1436,30 @@ impl SSMOps {
                     }
                 },
                 RusotoError::HttpDispatch(h_err) => {
-                    println!("{:?}", h_err);
+                    debug!("{:?}", h_err);
                     Err(failure::err_msg(h_err.to_string()))
                 }
                 RusotoError::Credentials(c_err) => {
-                    println!("{:?}", c_err);
+                    debug!("{:?}", c_err);
                     Err(failure::err_msg(c_err.to_string()))
                 }
                 RusotoError::Validation(v_err) => {
-                    println!("{:?}", v_err);
+                    debug!("{:?}", v_err);
                     Err(failure::err_msg(v_err.to_string()))
                 }
                 RusotoError::ParseError(p_err) => {
-                    println!("{:?}", p_err);
+                    debug!("{:?}", p_err);
                     Err(failure::err_msg(p_err.to_string()))
                 }
                 RusotoError::Unknown(_) => Err(failure::err_msg("Unknown Error.")),
             },
             Ok(res) => {
-                println!("{:?}", res);
                 let parm = res.parameter.unwrap();
-                Ok(SSMParameter{name: parm.name, value: parm.value, p_type: parm.type_, version: parm.version})
+                debug!(
+                    "GetParameter: {} - Version: {:?}",
+                    parameter.name, parm.version
+                );
+                Ok(SSMParameter::from(parm))
             }
         }
     }
@@ -337,20 +1486,49 @@ impl SSMOps {
     //   "Type": "string",
     //   "Value": "string"
     /// }
-    ///    
-
-    fn put_one(&self, parameter: SSMParameter, overwrite: bool) -> Result<(), Error> {
+    pub fn put_one(&self, req: SSMPutParameterRequest) -> Result<(), Error> {
+        let parameter = req.parameter;
+        if let Some(name) = parameter.name.as_deref().filter(|n| is_reserved_name(n)) {
+            bail!(
+                "The parameter '{}' is reserved (names starting with the word aws or ssm are public parameters or Secrets Manager references), it is read only.",
+                name
+            );
+        }
+        self.guard(parameter.name.as_deref().unwrap_or_default())?;
+        let value_size = parameter.value.as_ref().map_or(0, String::len);
+        let tier = select_tier(req.tier.as_deref(), value_size, req.policies.is_some())
+            .map_err(failure::err_msg)?;
+        if req.tier.is_none() && tier.is_some() {
+            warn!(
+                "{}: {}, writing it as an Advanced tier parameter (charged, and it can't go back to Standard)",
+                parameter.name.as_deref().unwrap_or_default(),
+                if value_size > STANDARD_TIER_MAX_VALUE_SIZE {
+                    format!(
+                        "the value is {} bytes, over the Standard tier {} bytes",
+                        value_size, STANDARD_TIER_MAX_VALUE_SIZE
+                    )
+                } else {
+                    "policies need the Advanced tier".to_string()
+                }
+            );
+        }
+        // PutParameter refuses Tags together with Overwrite, they are added afterwards
+        let (tags, tags_after_put) = match (req.overwrite, req.tags.is_empty()) {
+            (_, true) => (None, vec![]),
+            (false, false) => (Some(to_tags(&req.tags)), vec![]),
+            (true, false) => (None, req.tags),
+        };
         let input: PutParameterRequest = PutParameterRequest {
-            allowed_pattern: None,
-            description: None,
-            key_id: None,
+            allowed_pattern: req.allowed_pattern,
+            description: req.description,
+            key_id: req.key_id,
             name: parameter
                 .name
                 .expect("Put Parameter: Invalid Parameter Name in Request."),
-            overwrite: Some(overwrite),
-            policies: None,
-            tags: None,
-            tier: Some("Standard".to_string()),
+            overwrite: Some(req.overwrite),
+            policies: req.policies,
+            tags,
+            tier,
             type_: parameter
                 .p_type
                 .expect("Put Parameter: Invalid Parameter Type in Request."),
@@ -358,7 +1536,37 @@ impl SSMOps {
                 .value
                 .expect("Put Parameter: Invalid Parameter Value in Request."),
         };
+        if let Some(planner) = &self.planner {
+            let from_version = self.current_version(&input.name)?;
+            if from_version.is_some() && !req.overwrite {
+                bail!("The parameter \'{}\' already exists. You can't create duplicate parameters. Set --overwrite if you want change the same parameter.", input.name);
+            }
+            planner.record(PlannedOperation {
+                action: if from_version.is_some() {
+                    PlannedAction::Overwrite
+                } else {
+                    PlannedAction::Create
+                },
+                name: input.name.clone(),
+                p_type: Some(input.type_.clone()),
+                value: Some(MASKED_VALUE.to_string()),
+                from_version,
+                to_version: Some(from_version.unwrap_or(0) + 1),
+                detail: None,
+            });
+            if !tags_after_put.is_empty() {
+                self.add_tags(&input.name, &tags_after_put)?;
+            }
+            return Ok(());
+        }
 
+        // Only an overwrite has a version before, None when the parameter didn't exist
+        let old_version = if req.overwrite {
+            self.audited_versions(std::slice::from_ref(&input.name))?
+                .remove(&input.name)
+        } else {
+            None
+        };
         match self.ssm_client.put_parameter(input.clone()).sync() {
             Err(err) => match err {
                 RusotoError::Service(s_err) => match s_err as PutParameterError {
@@ -409,25 +1617,38 @@ impl SSMOps {
                     ),
                 },
                 RusotoError::HttpDispatch(h_err) => {
-                    println!("{:?}", h_err);
+                    debug!("{:?}", h_err);
                     Err(failure::err_msg(h_err.to_string()))
                 }
                 RusotoError::Credentials(c_err) => {
-                    println!("{:?}", c_err);
+                    debug!("{:?}", c_err);
                     Err(failure::err_msg(c_err.to_string()))
                 }
                 RusotoError::Validation(v_err) => {
-                    println!("{:?}", v_err);
+                    debug!("{:?}", v_err);
                     Err(failure::err_msg(v_err.to_string()))
                 }
                 RusotoError::ParseError(p_err) => {
-                    println!("{:?}", p_err);
+                    debug!("{:?}", p_err);
                     Err(failure::err_msg(p_err.to_string()))
                 }
                 RusotoError::Unknown(_) => Err(failure::err_msg("Unknown Error.")),
             },
             Ok(res) => {
-                println!("{:?}", res);
+                debug!(
+                    "PutParameter: {} - Version: {:?}",
+                    input.name, res.version
+                );
+                self.audit(
+                    "put",
+                    &input.name,
+                    (old_version, res.version),
+                    Some(&input.value),
+                    vec![],
+                );
+                if !tags_after_put.is_empty() {
+                    self.add_tags(&input.name, &tags_after_put)?;
+                }
                 //                let parm = res.parameter.unwrap();
                 //                Ok(SSMParameter{name: parm.name, value: parm.value, p_type: parm.type_, version: parm.version})
                 Ok(())
@@ -439,30 +1660,42 @@ impl SSMOps {
         &self,
         template: Option<&handlebars::template::Template>,
     ) -> Result<Vec<String>, Error> {
-        let result: Vec<String> = template
+        let literal = |p: Option<&template::Parameter>| match p {
+            Some(template::Parameter::Literal(json)) => Some(json.clone()),
+            _ => None,
+        };
+        let mut result: Vec<String> = Vec::new();
+        for element in &template
             .ok_or(format_err!("Template Unavailable"))?
             .elements
-            .iter()
-            .filter_map(|element| match element {
+        {
+            match element {
                 template::TemplateElement::Expression(he)
-                    if he.name.as_name().unwrap() == "ssm".to_owned() && he.params.len() == 1 =>
+                    if he.name.as_name().unwrap() == "ssm" && he.params.len() == 1 =>
                 {
-                    match &he.params[0] {
-                        handlebars::template::Parameter::Literal(a) => a.as_str().map(String::from),
-                        _ => None,
+                    if let Some(name) = literal(he.params.first()) {
+                        result.push(
+                            selector_key(
+                                &name,
+                                literal(he.hash.get("version")).as_ref(),
+                                literal(he.hash.get("label")).as_ref(),
+                            )
+                            .map_err(failure::err_msg)?,
+                        );
                     }
                 }
-                _ => None,
-            })
-            .collect();
+                _ => {}
+            }
+        }
 
         Ok(result)
     }
 
+    /// Values and versions (when known) keyed by requested name
     fn retrieve_parameters(
         &self,
         parameters: Vec<String>,
-    ) -> Result<HashMap<String, String>, Error> {
+    ) -> Result<(HashMap<String, String>, ParameterVersions), Error> {
         let gp = SSMParametersRequest {
             with_decryption: Some(true),
             names: parameters,
@@ -470,20 +1703,122 @@ impl SSMOps {
 
         match self.get_parameters(&gp) {
             Ok(result) => {
-                if result.invalid_parameters.len() > 0 {
-                    return Err(failure::err_msg(format_err!(
+                if !result.invalid_parameters.is_empty() {
+                    Err(format_err!(
                         "Invalid Parameters: {}",
                         result.invalid_parameters.join(", "),
-                    )));
+                    ))
                 } else {
                     let mut data: HashMap<String, String> = HashMap::new();
+                    let mut versions = ParameterVersions::new();
+                    // Keyed as requested: SSM answers /app/db:stable with Name /app/db, Selector :stable
                     result.parameters.iter().for_each(|p| {
-                        data.insert(p.name.clone().unwrap(), p.value.clone().unwrap());
+                        let key = format!(
+                            "{}{}",
+                            p.name.clone().unwrap(),
+                            p.selector.clone().unwrap_or_default()
+                        );
+                        if let Some(version) = p.version {
+                            versions.insert(key.clone(), version);
+                        }
+                        data.insert(key, p.value.clone().unwrap());
                     });
-                    Ok(data.clone())
+                    Ok((data, versions))
                 }
             }
             Err(e) => Err(e),
         }
     }
 }
+
+/// `{{ssm "/app/db" version=3}}` / `{{ssm "/app/db" label="stable"}}` => /app/db:3 / /app/db:stable
+fn selector_key(
+    name: &Json,
+    version: Option<&Json>,
+    label: Option<&Json>,
+) -> Result<String, String> {
+    let name = name
+        .as_str()
+        .ok_or_else(|| format!("SSM Parameter name must be a string: {}", name))?;
+    let version = match version {
+        None => None,
+        Some(v) => Some(
+            v.as_i64()
+                .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                .ok_or_else(|| format!("{}: invalid version {}", name, v))?,
+        ),
+    };
+    let label = match label {
+        None => None,
+        Some(l) => Some(
+            l.as_str()
+                .ok_or_else(|| format!("{}: invalid label {}", name, l))?,
+        ),
+    };
+    with_selector(name, version, label)
+}
+
+fn to_parameter_filters(filters: &[SSMParameterFilter]) -> Option<Vec<ParameterStringFilter>> {
+    if filters.is_empty() {
+        None
+    } else {
+        Some(filters.iter().map(ParameterStringFilter::from).collect())
+    }
+}
+
+fn to_tags(tags: &[(String, String)]) -> Vec<Tag> {
+    tags.iter()
+        .map(|(key, value)| Tag {
+            key: key.clone(),
+            value: value.clone(),
+        })
+        .collect()
+}
+
+fn profile_provider(profile: &str) -> Result<ProfileProvider, Error> {
+    let mut credentials = ProfileProvider::new()?;
+    credentials.set_profile(profile);
+    Ok(credentials)
+}
+
+fn assume_role_client<P>(base: P, role_arn: &str, region: Region) -> Result<Client, Error>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    let sts = StsClient::new_with(HttpClient::new()?, base, region);
+    let credentials = AutoRefreshingProvider::new(StsAssumeRoleSessionCredentialsProvider::new(
+        sts,
+        role_arn.to_string(),
+        "ssm_helper".to_string(),
+        None,
+        None,
+        None,
+        None,
+    ))?;
+    Ok(Client::new_with(credentials, HttpClient::new()?))
+}
+
+/// Response body as JSON, error responses become their `message` (or raw body)
+// RusotoError is large, but it is the error type sign_and_dispatch expects
+#[allow(clippy::result_large_err)]
+fn json_response(
+    response: HttpResponse,
+) -> Box<dyn Future<Item = Json, Error = RusotoError<String>> + Send> {
+    Box::new(
+        response
+            .buffer()
+            .from_err()
+            .and_then(|response: BufferedHttpResponse| {
+                if response.status.is_success() {
+                    Ok(serde_json::from_slice(&response.body)?)
+                } else {
+                    let body: Json = serde_json::from_slice(&response.body).unwrap_or_default();
+                    Err(RusotoError::Service(match body["message"].as_str() {
+                        Some(message) => format!("{}: {}", body["__type"], message),
+                        None => response.body_as_str().to_string(),
+                    }))
+                }
+            }),
+    )
+}
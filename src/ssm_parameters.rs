@@ -1,9 +1,114 @@
-#[derive(Serialize, Debug)]
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use regex::Regex;
+use rusoto_ssm::{Parameter, ParameterInlinePolicy, ParameterMetadata, ParameterStringFilter};
+
+use crate::policies::policy_summary;
+use crate::sync::SyncFilter;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct SSMParameter {
     pub name: Option<String>,
     pub p_type: Option<String>,
     pub value: Option<String>,
     pub version: Option<i64>,
+    pub arn: Option<String>,
+    pub last_modified_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_result: Option<String>,
+}
+
+impl From<Parameter> for SSMParameter {
+    fn from(p: Parameter) -> Self {
+        SSMParameter {
+            name: p.name,
+            p_type: p.type_,
+            value: p.value,
+            version: p.version,
+            arn: p.arn,
+            last_modified_date: p.last_modified_date.map(format_timestamp),
+            selector: p.selector,
+            source_result: p.source_result,
+        }
+    }
+}
+
+/// DescribeParameters view of a Parameter, everything but the value
+#[derive(Serialize, Debug, Default)]
+pub struct SSMParameterMetadata {
+    pub name: Option<String>,
+    pub p_type: Option<String>,
+    pub version: Option<i64>,
+    pub description: Option<String>,
+    pub key_id: Option<String>,
+    pub tier: Option<String>,
+    pub allowed_pattern: Option<String>,
+    pub last_modified_date: Option<String>,
+    pub last_modified_user: Option<String>,
+    pub policies: Vec<SSMParameterPolicy>,
+}
+
+impl From<ParameterMetadata> for SSMParameterMetadata {
+    fn from(p: ParameterMetadata) -> Self {
+        SSMParameterMetadata {
+            name: p.name,
+            p_type: p.type_,
+            version: p.version,
+            description: p.description,
+            key_id: p.key_id,
+            tier: p.tier,
+            allowed_pattern: p.allowed_pattern,
+            last_modified_date: p.last_modified_date.map(format_timestamp),
+            last_modified_user: p.last_modified_user,
+            policies: p
+                .policies
+                .unwrap_or_default()
+                .into_iter()
+                .map(SSMParameterPolicy::from)
+                .collect(),
+        }
+    }
+}
+
+impl SSMParameterMetadata {
+    /// Policies back into the JSON array PutParameter expects, None if there are none
+    pub fn policies_json(&self) -> Option<String> {
+        let texts: Vec<&str> = self
+            .policies
+            .iter()
+            .filter_map(|p| p.policy_text.as_deref())
+            .collect();
+        if texts.is_empty() {
+            None
+        } else {
+            Some(format!("[{}]", texts.join(",")))
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SSMParameterPolicy {
+    pub policy_type: Option<String>,
+    pub policy_status: Option<String>,
+    pub policy_text: Option<String>,
+    /// Readable form of policy_text, ex: Expires at 2030-01-01T00:00:00.000Z
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl From<ParameterInlinePolicy> for SSMParameterPolicy {
+    fn from(p: ParameterInlinePolicy) -> Self {
+        SSMParameterPolicy {
+            policy_type: p.policy_type,
+            policy_status: p.policy_status,
+            summary: p.policy_text.as_deref().and_then(policy_summary),
+            policy_text: p.policy_text,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -23,6 +128,131 @@ pub struct SSMParametersByPathRequest {
     pub path: String,
     pub with_decryption: Option<bool>,
     pub recursive: Option<bool>,
+    pub parameter_filters: Vec<SSMParameterFilter>,
+}
+
+#[derive(Debug)]
+pub struct SSMDescribeParametersRequest {
+    pub names: Vec<String>,
+    pub filters: Vec<SSMParameterFilter>,
+}
+
+/// Server side filters (DescribeParameters, GetParametersByPath) plus
+/// the ones SSM can't do for us: label (DescribeParameters), name regex and date.
+#[derive(Debug, Default)]
+pub struct SSMFindParametersRequest {
+    pub filters: Vec<SSMParameterFilter>,
+    pub label: Option<String>,
+    pub name_regex: Option<Regex>,
+    pub modified_since: Option<DateTime<Utc>>,
+}
+
+/// ParameterStringFilter, ex: Key=Type, Option=Equals, Values=[SecureString]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SSMParameterFilter {
+    pub key: String,
+    pub option: Option<String>,
+    pub values: Vec<String>,
+}
+
+impl SSMParameterFilter {
+    pub fn new(key: &str, option: &str, values: Vec<String>) -> Self {
+        SSMParameterFilter {
+            key: key.to_string(),
+            option: Some(option.to_string()),
+            values,
+        }
+    }
+}
+
+impl From<&SSMParameterFilter> for ParameterStringFilter {
+    fn from(f: &SSMParameterFilter) -> Self {
+        ParameterStringFilter {
+            key: f.key.clone(),
+            option: f.option.clone(),
+            values: Some(f.values.clone()),
+        }
+    }
+}
+
+impl FromStr for SSMParameterFilter {
+    type Err = String;
+
+    /// AWS CLI shorthand: Key=Type,Option=Equals,Values=String,SecureString
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut key: Option<String> = None;
+        let mut option: Option<String> = None;
+        let mut values: Vec<String> = Vec::new();
+        let mut in_values = false;
+        for token in s.split(',') {
+            if let Some(k) = token.strip_prefix("Key=") {
+                key = Some(k.to_string());
+                in_values = false;
+            } else if let Some(o) = token.strip_prefix("Option=") {
+                option = Some(o.to_string());
+                in_values = false;
+            } else if let Some(v) = token.strip_prefix("Values=") {
+                values.push(v.to_string());
+                in_values = true;
+            } else if in_values {
+                values.push(token.to_string());
+            } else {
+                return Err(format!("Invalid Parameter Filter: {}", s));
+            }
+        }
+        match key {
+            Some(key) if !key.is_empty() => Ok(SSMParameterFilter {
+                key,
+                option,
+                values,
+            }),
+            _ => Err(format!("Invalid Parameter Filter, Key required: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SSMPutParameterRequest {
+    pub parameter: SSMParameter,
+    pub overwrite: bool,
+    pub key_id: Option<String>,
+    pub description: Option<String>,
+    pub tier: Option<String>,
+    pub allowed_pattern: Option<String>,
+    /// JSON array of policies, as PutParameter expects them
+    pub policies: Option<String>,
+    pub tags: Vec<(String, String)>,
+}
+
+/// Labels on a parameter version, ex: stable => /app/db:stable
+#[derive(Debug)]
+pub struct SSMLabelRequest {
+    pub name: String,
+    pub version: Option<i64>,
+    pub labels: Vec<String>,
+}
+
+/// Single parameter (clone) or prefix (clone-all) copy
+#[derive(Debug, Clone)]
+pub struct SSMCloneRequest {
+    pub origin: String,
+    pub destination: String,
+    pub overwrite: bool,
+    pub key_id: Option<String>,
+    /// Copy the source tags
+    pub copy_tags: bool,
+    /// Extra tags, override source tags with the same key
+    pub tags: Vec<(String, String)>,
+    /// Tier override, the source tier if None
+    pub tier: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SSMSyncRequest {
+    pub source_path: String,
+    pub destination_path: String,
+    pub filter: SyncFilter,
+    pub tier: Option<String>,
 }
 
 #[derive(Debug, Default)]
@@ -35,3 +265,144 @@ pub struct SSMParametersResult {
 pub struct SSMRequestError {
     pub reason: String,
 }
+
+impl fmt::Display for SSMRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+/// Secrets Manager secrets read through Parameter Store, GetParameter only and always decrypted
+pub const SECRETS_MANAGER_PREFIX: &str = "/aws/reference/secretsmanager/";
+
+pub fn is_secrets_manager_reference(name: &str) -> bool {
+    name.starts_with(SECRETS_MANAGER_PREFIX)
+}
+
+/// Names starting with the word aws or ssm (any case, ex: /aws/.., ssm-..) belong to AWS:
+/// public parameters and references. /awsome/app is an ordinary name.
+pub fn is_reserved_name(name: &str) -> bool {
+    let first = name.trim_start_matches('/').to_lowercase();
+    ["aws", "ssm"].iter().any(|word| {
+        first
+            .strip_prefix(word)
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric()))
+    })
+}
+
+/// Why Parameter Store refuses a version label, ex: "1bad" => "can't start with a number"
+pub fn invalid_label_reason(label: &str) -> &'static str {
+    let lower = label.to_lowercase();
+    if label.chars().count() > 100 {
+        "over 100 characters"
+    } else if label.starts_with(|c: char| c.is_ascii_digit()) {
+        "can't start with a number"
+    } else if lower.starts_with("aws") || lower.starts_with("ssm") {
+        "can't start with aws or ssm"
+    } else if !label
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+    {
+        "only letters, numbers, periods, hyphens and underscores"
+    } else {
+        "refused by Parameter Store"
+    }
+}
+
+/// A key of a JSON object value, ex: a Secrets Manager secret {"username": .., "password": ..}
+pub fn json_key(value: &str, key: &str) -> Result<String, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(value).map_err(|_| format!("Not a JSON value, no key '{}'", key))?;
+    match json.get(key) {
+        Some(serde_json::Value::String(s)) => Ok(s.clone()),
+        Some(v) => Ok(v.to_string()),
+        None => Err(format!("Key '{}' not found", key)),
+    }
+}
+
+/// Largest value (bytes) of a Standard tier parameter
+pub const STANDARD_TIER_MAX_VALUE_SIZE: usize = 4096;
+/// Largest value (bytes) of an Advanced tier parameter
+pub const ADVANCED_TIER_MAX_VALUE_SIZE: usize = 8192;
+
+/// Tier for a write: the requested one, or Advanced when the value size or policies need it
+/// and nothing was requested (None lets SSM apply the account default tier).
+/// Intelligent-Tiering leaves the choice to SSM.
+pub fn select_tier(
+    requested: Option<&str>,
+    value_size: usize,
+    has_policies: bool,
+) -> Result<Option<String>, String> {
+    if value_size > ADVANCED_TIER_MAX_VALUE_SIZE {
+        return Err(format!(
+            "The value is {} bytes, the limit is {} bytes (Advanced tier).",
+            value_size, ADVANCED_TIER_MAX_VALUE_SIZE
+        ));
+    }
+    let needs_advanced = value_size > STANDARD_TIER_MAX_VALUE_SIZE || has_policies;
+    match requested {
+        Some("Standard") if needs_advanced => Err(format!(
+            "The Standard tier allows neither values over {} bytes ({} requested) nor policies, use --tier Advanced or Intelligent-Tiering.",
+            STANDARD_TIER_MAX_VALUE_SIZE, value_size
+        )),
+        None if needs_advanced => Ok(Some("Advanced".to_string())),
+        _ => Ok(requested.map(String::from)),
+    }
+}
+
+/// Name with a selector, `/app/db:stable` or `/app/db:3`, SSM resolves it on get.
+/// At most one of version / label, and not on a name already carrying a selector.
+pub fn with_selector(
+    name: &str,
+    version: Option<i64>,
+    label: Option<&str>,
+) -> Result<String, String> {
+    match (version, label) {
+        (None, None) => Ok(name.to_string()),
+        (Some(_), Some(_)) => Err(format!("{}: version and label are exclusive", name)),
+        _ if name.contains(':') => Err(format!("{}: already has a selector", name)),
+        (Some(version), None) => Ok(format!("{}:{}", name, version)),
+        (None, Some(label)) => Ok(format!("{}:{}", name, label)),
+    }
+}
+
+/// Parameter name relative to a path prefix: ("/app/prod", "/app/prod/db/host") => "db/host"
+pub fn relative_name<'a>(path: &str, name: &'a str) -> &'a str {
+    let prefix = path.trim_end_matches('/');
+    match name.strip_prefix(prefix) {
+        Some(rest) if prefix.is_empty() || rest.is_empty() || rest.starts_with('/') => {
+            rest.trim_start_matches('/')
+        }
+        _ => name.trim_start_matches('/'),
+    }
+}
+
+/// Hierarchy holding every name a Name BeginsWith filter matches, ex: /app/pro => /app,
+/// "/" without one
+pub fn begins_with_path(filters: &[SSMParameterFilter]) -> String {
+    filters
+        .iter()
+        .filter(|f| f.key == "Name" && f.option.as_deref() == Some("BeginsWith"))
+        .filter_map(|f| f.values.first())
+        .find_map(|v| v.rfind('/').map(|end| v[..end].to_string()))
+        .filter(|path| path.starts_with('/'))
+        .unwrap_or_else(|| "/".to_string())
+}
+
+/// Levels below a path prefix: ("/app", "/app/db/host") => 2
+pub fn relative_depth(path: &str, name: &str) -> usize {
+    relative_name(path, name)
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .count()
+}
+
+/// SSM returns dates as fractional epoch seconds, render them as RFC3339 (UTC)
+pub fn format_timestamp(epoch: f64) -> String {
+    let secs = epoch.trunc() as i64;
+    let nanos = (epoch.fract() * 1_000_000_000.0) as u32;
+    match Utc.timestamp_opt(secs, nanos).single() {
+        Some(date) => date.to_rfc3339_opts(SecondsFormat::Secs, true),
+        None => epoch.to_string(),
+    }
+}
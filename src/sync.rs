@@ -0,0 +1,110 @@
+use glob::{MatchOptions, Pattern};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::diff::MASKED_VALUE;
+use crate::ssm_parameters::{relative_name, SSMParameter};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncAction {
+    Create,
+    Overwrite,
+    Delete,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncOperation {
+    pub action: SyncAction,
+    pub name: String,
+    pub p_type: Option<String>,
+    #[serde(skip)]
+    pub value: Option<String>,
+}
+
+impl fmt::Display for SyncOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            SyncAction::Create => "create",
+            SyncAction::Overwrite => "overwrite",
+            SyncAction::Delete => "delete",
+        };
+        write!(
+            f,
+            "{:<9} {} [{}]",
+            action,
+            self.name,
+            self.p_type.clone().unwrap_or_default()
+        )?;
+        if self.action != SyncAction::Delete {
+            write!(f, " = {}", MASKED_VALUE)?;
+        }
+        Ok(())
+    }
+}
+
+/// --include / --exclude glob patterns, matched against names relative to the prefixes
+#[derive(Debug, Default)]
+pub struct SyncFilter {
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl SyncFilter {
+    /// `*` and `?` stay within a path segment, `**` crosses them
+    pub fn matches(&self, key: &str) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let hit = |p: &Pattern| p.matches_with(key, options);
+        (self.include.is_empty() || self.include.iter().any(hit)) && !self.exclude.iter().any(hit)
+    }
+}
+
+/// Operations making `destination` mirror `source`: missing keys are created,
+/// different ones overwritten, extraneous ones deleted, identical ones left alone.
+pub fn plan_sync(
+    source_path: &str,
+    source: Vec<SSMParameter>,
+    destination_path: &str,
+    destination: Vec<SSMParameter>,
+    filter: &SyncFilter,
+) -> Vec<SyncOperation> {
+    let index = |path: &str, side: Vec<SSMParameter>| -> BTreeMap<String, SSMParameter> {
+        side.into_iter()
+            .filter_map(|p| {
+                let key = relative_name(path, p.name.as_ref()?).to_string();
+                Some((key, p))
+            })
+            .filter(|(key, _)| filter.matches(key))
+            .collect()
+    };
+    let source = index(source_path, source);
+    let mut destination = index(destination_path, destination);
+    let destination_name =
+        |key: &str| format!("{}/{}", destination_path.trim_end_matches('/'), key);
+
+    let mut operations: Vec<SyncOperation> = Vec::new();
+    for (key, s) in source {
+        let action = match destination.remove(&key) {
+            None => SyncAction::Create,
+            Some(d) if d.p_type != s.p_type || d.value != s.value => SyncAction::Overwrite,
+            Some(_) => continue,
+        };
+        operations.push(SyncOperation {
+            action,
+            name: destination_name(&key),
+            p_type: s.p_type,
+            value: s.value,
+        });
+    }
+    operations.extend(destination.into_values().map(|d| SyncOperation {
+        action: SyncAction::Delete,
+        name: d.name.unwrap_or_default(),
+        p_type: d.p_type,
+        value: None,
+    }));
+
+    operations
+}
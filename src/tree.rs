@@ -0,0 +1,148 @@
+use serde_json::map::Map;
+use serde_json::value::Value as Json;
+use std::collections::BTreeMap;
+
+use crate::ssm_parameters::{relative_name, SSMParameter};
+
+/// Key holding the value of a node that is both a parameter and a path,
+/// ex: /app/db and /app/db/host => {"db": {"_value": "...", "host": "..."}}
+pub const NODE_VALUE_KEY: &str = "_value";
+
+#[derive(Debug, Default)]
+pub struct TreeOptions {
+    pub types: bool,
+    pub versions: bool,
+    pub counts: bool,
+}
+
+/// Parameter hierarchy below a path, built from GetParametersByPath results
+#[derive(Debug)]
+pub struct ParameterTree {
+    path: String,
+    root: TreeNode,
+}
+
+#[derive(Debug, Default)]
+struct TreeNode {
+    children: BTreeMap<String, TreeNode>,
+    parameter: Option<SSMParameter>,
+}
+
+impl TreeNode {
+    fn count(&self) -> usize {
+        self.parameter.iter().count() + self.children.values().map(TreeNode::count).sum::<usize>()
+    }
+
+    fn count_paths(&self) -> usize {
+        self.children
+            .values()
+            .filter(|c| !c.children.is_empty())
+            .map(|c| 1 + c.count_paths())
+            .sum()
+    }
+
+    fn label(&self, name: &str, options: &TreeOptions) -> String {
+        let mut label = name.to_string();
+        if let Some(p) = &self.parameter {
+            let mut annotations: Vec<String> = Vec::new();
+            if options.types {
+                annotations.push(p.p_type.clone().unwrap_or_default());
+            }
+            if options.versions {
+                annotations.push(format!("v{}", p.version.unwrap_or_default()));
+            }
+            if !annotations.is_empty() {
+                label.push_str(&format!(" [{}]", annotations.join(" ")));
+            }
+        }
+        if options.counts && !self.children.is_empty() {
+            label.push_str(&format!(" ({})", self.count()));
+        }
+        label
+    }
+
+    fn render(&self, prefix: &str, options: &TreeOptions, out: &mut String) {
+        let last = self.children.len();
+        for (i, (name, child)) in self.children.iter().enumerate() {
+            let (branch, indent) = if i + 1 == last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            out.push_str(&format!(
+                "{}{}{}\n",
+                prefix,
+                branch,
+                child.label(name, options)
+            ));
+            child.render(&format!("{}{}", prefix, indent), options, out);
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let value = |p: &SSMParameter| p.value.clone().map_or(Json::Null, Json::String);
+        if self.children.is_empty() {
+            return self.parameter.as_ref().map_or(Json::Null, value);
+        }
+        let mut object = Map::new();
+        if let Some(p) = &self.parameter {
+            object.insert(NODE_VALUE_KEY.to_string(), value(p));
+        }
+        for (name, child) in &self.children {
+            object.insert(name.clone(), child.to_json());
+        }
+        Json::Object(object)
+    }
+}
+
+impl ParameterTree {
+    pub fn new(path: &str) -> Self {
+        ParameterTree {
+            path: path.to_string(),
+            root: TreeNode::default(),
+        }
+    }
+
+    pub fn insert(&mut self, parameter: SSMParameter) {
+        let name = match &parameter.name {
+            Some(name) => relative_name(&self.path, name).to_string(),
+            None => return,
+        };
+        let node = name
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .fold(&mut self.root, |node, segment| {
+                node.children.entry(segment.to_string()).or_default()
+            });
+        node.parameter = Some(parameter);
+    }
+
+    /// Unix `tree` like rendering
+    pub fn render(&self, options: &TreeOptions) -> String {
+        let mut out = self.root.label(&self.path, options);
+        out.push('\n');
+        self.root.render("", options, &mut out);
+        let paths = self.root.count_paths();
+        let parameters = self.root.count();
+        out.push_str(&format!(
+            "\n{} {}, {} {}",
+            paths,
+            if paths == 1 { "path" } else { "paths" },
+            parameters,
+            if parameters == 1 {
+                "parameter"
+            } else {
+                "parameters"
+            }
+        ));
+        out
+    }
+
+    /// Nested object, relative names split on '/', usable as a template context
+    pub fn to_json(&self) -> Json {
+        match self.root.to_json() {
+            Json::Null => Json::Object(Map::new()),
+            json => json,
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use failure::Error;
+
+use crate::output::write_atomically_private;
+use crate::ssm_ops::{ParameterVersions, SSMOps};
+
+/// template --watch
+#[derive(Debug, Clone)]
+pub struct SSMWatchRequest {
+    pub template_in: PathBuf,
+    pub template_out: PathBuf,
+    pub interval: Duration,
+    /// Run with `sh -c` after every re-render
+    pub on_change: Option<String>,
+    /// --on-change attempts after the first one
+    pub retries: u32,
+    /// Ceiling of the doubling delay between attempts / failed polls
+    pub max_backoff: Duration,
+}
+
+/// `base` doubled `attempt` times, never over `ceiling`
+pub fn backoff(base: Duration, attempt: u32, ceiling: Duration) -> Duration {
+    base.checked_mul(2u32.saturating_pow(attempt))
+        .map_or(ceiling, |d| d.min(ceiling))
+}
+
+/// Renders once, then re-renders when a referenced parameter's version moves
+pub struct TemplateWatcher {
+    /// Its disk cache (if any) is refreshed, never read: a cached version would hide a change
+    ssm: SSMOps,
+    req: SSMWatchRequest,
+    names: Vec<String>,
+    versions: ParameterVersions,
+    rendered: String,
+}
+
+impl TemplateWatcher {
+    pub fn new(ssm: &SSMOps, req: SSMWatchRequest) -> Result<Self, Error> {
+        let mut watcher = TemplateWatcher {
+            ssm: ssm.refreshing(),
+            req,
+            names: vec![],
+            versions: ParameterVersions::new(),
+            rendered: String::new(),
+        };
+        watcher.render()?;
+        Ok(watcher)
+    }
+
+    /// Render and write atomically if the output changed, true when it did
+    fn render(&mut self) -> Result<bool, Error> {
+        // Versions from the response that was rendered: a rotation in between is seen next poll
+        let (rendered, names, versions) = self.ssm.render_template(&self.req.template_in)?;
+        self.versions = versions;
+        self.names = names;
+        if rendered == self.rendered {
+            return Ok(false);
+        }
+        write_atomically_private(&self.req.template_out, rendered.as_bytes())?;
+        info!("Rendered {:?}", self.req.template_out);
+        self.rendered = rendered;
+        Ok(true)
+    }
+
+    /// One cheap version check (no decryption), re-rendering only if a version moved
+    pub fn poll(&mut self) -> Result<bool, Error> {
+        let versions = self.ssm.parameter_versions(&self.names)?;
+        if versions == self.versions {
+            trace!("No parameter change");
+            return Ok(false);
+        }
+        for (name, version) in &versions {
+            if self.versions.get(name) != Some(version) {
+                info!("{} changed: version {}", name, version);
+            }
+        }
+        self.render()
+    }
+
+    /// Poll forever, running --on-change after every re-render
+    pub fn run(&mut self) -> Result<(), Error> {
+        let mut failures: u32 = 0;
+        loop {
+            let delay = match failures {
+                0 => self.req.interval,
+                n => backoff(
+                    self.req.interval,
+                    n,
+                    self.req.max_backoff.max(self.req.interval),
+                ),
+            };
+            thread::sleep(delay);
+            match self.poll() {
+                Ok(changed) => {
+                    failures = 0;
+                    if let (true, Some(command)) = (changed, &self.req.on_change) {
+                        if let Err(e) =
+                            run_on_change(command, self.req.retries, self.req.max_backoff)
+                        {
+                            error!("{}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!("Watch: {} (failure {})", e, failures);
+                }
+            }
+        }
+    }
+}
+
+/// `sh -c command`, retried with a doubling delay (1s, 2s, ...) up to `max_backoff`
+pub fn run_on_change(command: &str, retries: u32, max_backoff: Duration) -> Result<(), Error> {
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            let delay = backoff(Duration::from_secs(1), attempt - 1, max_backoff);
+            debug!("--on-change retry {} in {:?}", attempt, delay);
+            thread::sleep(delay);
+        }
+        match Command::new("sh").arg("-c").arg(command).status() {
+            Ok(status) if status.success() => {
+                info!("--on-change: {}", command);
+                return Ok(());
+            }
+            Ok(status) => warn!("--on-change '{}' failed: {}", command, status),
+            Err(e) => warn!("--on-change '{}' failed: {}", command, e),
+        }
+    }
+
+    bail!("--on-change '{}' failed {} time(s)", command, retries + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk_cache::{CacheMode, DiskCache};
+    use crate::memory_backend::{MemoryBackend, StoredParameter};
+    use crate::ssm_parameters::SSMParametersRequest;
+    use std::fs;
+    use std::path::Path;
+
+    fn pass(version: i64, value: &str) -> StoredParameter {
+        StoredParameter {
+            name: "/app/db/pass".to_string(),
+            p_type: "SecureString".to_string(),
+            value: value.to_string(),
+            version,
+            ..Default::default()
+        }
+    }
+
+    /// A fresh temporary directory holding `in.tpl`, which renders /app/db/pass
+    fn template_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ssm_helper_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("in.tpl"), r#"pass={{ssm "/app/db/pass"}}"#).unwrap();
+        dir
+    }
+
+    /// Watching `dir`/in.tpl into `dir`/out
+    fn watch(ssm: &SSMOps, dir: &Path) -> TemplateWatcher {
+        TemplateWatcher::new(
+            ssm,
+            SSMWatchRequest {
+                template_in: dir.join("in.tpl"),
+                template_out: dir.join("out"),
+                interval: Duration::from_secs(30),
+                on_change: None,
+                retries: 0,
+                max_backoff: Duration::from_secs(60),
+            },
+        )
+        .unwrap()
+    }
+
+    fn source() -> (MemoryBackend, SSMOps) {
+        let backend = MemoryBackend::default();
+        backend.insert(pass(1, "s3cr3t"));
+        let ssm = SSMOps::with_client("us-east-1", backend.client());
+        (backend, ssm)
+    }
+
+    #[test]
+    fn first_render_writes_the_output() {
+        let (_backend, ssm) = source();
+        let dir = template_dir("watch_first");
+        watch(&ssm, &dir);
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "pass=s3cr3t");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn poll_without_a_version_change_does_not_render() {
+        let (_backend, ssm) = source();
+        let dir = template_dir("watch_unchanged");
+        let mut watcher = watch(&ssm, &dir);
+        assert!(!watcher.poll().unwrap());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn poll_renders_once_after_a_version_moves() {
+        let (backend, ssm) = source();
+        let dir = template_dir("watch_moved");
+        let mut watcher = watch(&ssm, &dir);
+        backend.insert(pass(2, "r0tated"));
+        assert!(watcher.poll().unwrap());
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "pass=r0tated");
+        assert!(!watcher.poll().unwrap());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rendered_versions_come_with_the_values() {
+        let (backend, ssm) = source();
+        let dir = template_dir("watch_versions");
+        backend.insert(pass(4, "r0tated"));
+        let (rendered, _, versions) = ssm.render_template(&dir.join("in.tpl")).unwrap();
+        assert_eq!(rendered, "pass=r0tated");
+        assert_eq!(versions["/app/db/pass"], 4);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn render_leaves_no_temporary_file() {
+        let (backend, ssm) = source();
+        let dir = template_dir("watch_tmp");
+        let mut watcher = watch(&ssm, &dir);
+        backend.insert(pass(2, "r0tated"));
+        watcher.poll().unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_output_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let (_backend, ssm) = source();
+        let dir = template_dir("watch_private");
+        watch(&ssm, &dir);
+        let mode = fs::metadata(dir.join("out")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn poll_sees_a_change_behind_a_fresh_cache_entry() {
+        let backend = MemoryBackend::default();
+        backend.insert(pass(1, "s3cr3t"));
+        let dir = template_dir("watch_cache");
+        let cache = DiskCache::new(
+            &dir.join("cache"),
+            &dir.join("cache-key.txt"),
+            CacheMode::Prefer,
+            Duration::from_secs(900),
+            None,
+        )
+        .unwrap()
+        .with_account("123456789012");
+        let ssm = SSMOps::with_client("us-east-1", backend.client()).disk_cache(Some(cache));
+        // Fresh entries for the value and the version check
+        for decrypt in &[true, false] {
+            ssm.get_parameters(&SSMParametersRequest {
+                names: vec!["/app/db/pass".to_string()],
+                with_decryption: Some(*decrypt),
+            })
+            .unwrap();
+        }
+
+        let mut watcher = watch(&ssm, &dir);
+        backend.insert(pass(2, "r0tated"));
+        assert!(watcher.poll().unwrap());
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "pass=r0tated");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_ceiling() {
+        let secs = Duration::from_secs;
+        assert_eq!(backoff(secs(1), 0, secs(60)), secs(1));
+        assert_eq!(backoff(secs(1), 3, secs(60)), secs(8));
+        assert_eq!(backoff(secs(30), 4, secs(300)), secs(300));
+        assert_eq!(backoff(secs(1), 200, secs(300)), secs(300));
+    }
+
+    #[test]
+    fn on_change_fails_after_its_retries() {
+        assert!(run_on_change("true", 0, Duration::from_secs(0)).is_ok());
+        assert!(run_on_change("exit 1", 2, Duration::from_secs(0)).is_err());
+    }
+}